@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+use crate::chain::transaction::Transaction;
+use crate::storage::{
+    backend::Storage,
+    trie::{PatriciaTrie, TrieError},
+};
+use crate::utils::conversion::to_hex;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    InsufficientBalance,
+    InvalidNonce,
+    BalanceOverflow,
+    ImmatureReward,
+    CorruptAccount(Vec<u8>),
+    RootMismatch,
+    Trie(TrieError),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InsufficientBalance => write!(f, "insufficient balance"),
+            StateError::InvalidNonce => write!(f, "invalid nonce"),
+            StateError::BalanceOverflow => write!(f, "balance overflow"),
+            StateError::ImmatureReward => write!(f, "spends a coinbase reward before it matured"),
+            StateError::CorruptAccount(address) => {
+                write!(f, "stored account {} could not be decoded", to_hex(address))
+            }
+            StateError::RootMismatch => write!(f, "resulting state root does not match"),
+            StateError::Trie(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Account {
+    fn encode(&self) -> Vec<u8> {
+        [self.balance.to_be_bytes(), self.nonce.to_be_bytes()].concat()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 16 {
+            return None;
+        }
+        Some(Self {
+            balance: u64::from_be_bytes(bytes[..8].try_into().ok()?),
+            nonce: u64::from_be_bytes(bytes[8..].try_into().ok()?),
+        })
+    }
+}
+
+/// Root the state had before a block was applied. Trie nodes are never
+/// overwritten, so restoring it undoes the block.
+#[derive(Debug, Clone, Copy)]
+pub struct StateUndo {
+    previous_root: [u8; 32],
+}
+
+/// Account balances and nonces kept in a Patricia trie keyed by address.
+pub struct AccountState {
+    trie: PatriciaTrie,
+}
+
+impl AccountState {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self {
+            trie: PatriciaTrie::new(storage),
+        }
+    }
+
+    pub fn from_allocations(
+        storage: Box<dyn Storage>,
+        allocations: &[(Vec<u8>, u64)],
+    ) -> Result<Self, StateError> {
+        let mut state = Self::new(storage);
+        for (address, balance) in allocations {
+            let mut account = state.get_account(address)?;
+            account.balance = account.balance.saturating_add(*balance);
+            state.put_account(address, &account)?;
+        }
+        state.trie.commit().map_err(StateError::Trie)?;
+        Ok(state)
+    }
+
+    pub fn get_account(&mut self, address: &[u8]) -> Result<Account, StateError> {
+        match self.trie.get(address).map_err(StateError::Trie)? {
+            Some(bytes) => {
+                Account::decode(&bytes).ok_or_else(|| StateError::CorruptAccount(address.to_vec()))
+            }
+            None => Ok(Account::default()),
+        }
+    }
+
+    fn put_account(&mut self, address: &[u8], account: &Account) -> Result<(), StateError> {
+        self.trie
+            .insert(address, &account.encode())
+            .map_err(StateError::Trie)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.trie.root()
+    }
+
+    /// Trie nodes proving the account stored under `address`, or that there
+    /// is none, against the current root.
+    pub fn prove_account(&mut self, address: &[u8]) -> Result<Vec<Vec<u8>>, StateError> {
+        self.trie.prove(address).map_err(StateError::Trie)
+    }
+
+    /// Applies every transaction of a block in order. Changes are staged and
+    /// only written back when the whole batch is valid, so a rejected block
+    /// leaves the state untouched. The returned undo log restores the previous
+    /// root when the block is disconnected during a reorg.
+    ///
+    /// Senders pay the value plus the fee; the fees reach the miner through
+    /// the coinbase, which only credits its receiver. The block has already
+    /// been checked to hold a single valid coinbase. `immature` holds, per address,
+    /// the coinbase rewards that cannot be spent yet, and no sender's
+    /// balance may drop below that amount. Nothing is written unless the
+    /// resulting root is `state_root`.
+    pub fn apply_transactions(
+        &mut self,
+        transactions: &[Transaction],
+        immature: &HashMap<Vec<u8>, u64>,
+        state_root: [u8; 32],
+    ) -> Result<StateUndo, StateError> {
+        let undo = self.stage_transactions(transactions, immature)?;
+        if self.root() != state_root {
+            self.discard(undo);
+            return Err(StateError::RootMismatch);
+        }
+        if let Err(e) = self.trie.commit() {
+            self.discard(undo);
+            return Err(StateError::Trie(e));
+        }
+        Ok(undo)
+    }
+
+    /// Root the state would have after `transactions`, computed from staged
+    /// trie nodes that are discarded again, so nothing reaches storage.
+    pub fn preview_root(
+        &mut self,
+        transactions: &[Transaction],
+        immature: &HashMap<Vec<u8>, u64>,
+    ) -> Result<[u8; 32], StateError> {
+        let undo = self.stage_transactions(transactions, immature)?;
+        let root = self.root();
+        self.discard(undo);
+        Ok(root)
+    }
+
+    /// Validates `transactions` and writes the resulting accounts to the
+    /// trie without committing its nodes.
+    fn stage_transactions(
+        &mut self,
+        transactions: &[Transaction],
+        immature: &HashMap<Vec<u8>, u64>,
+    ) -> Result<StateUndo, StateError> {
+        let mut staged: HashMap<Vec<u8>, Account> = HashMap::new();
+
+        for transaction in transactions {
+            let value = transaction
+                .total_value()
+                .ok_or(StateError::BalanceOverflow)?;
+            let cost = transaction
+                .total_cost()
+                .ok_or(StateError::BalanceOverflow)?;
+
+            if !transaction.is_coinbase() {
+                let mut sender = match staged.get(&transaction.from) {
+                    Some(account) => account.clone(),
+                    None => self.get_account(&transaction.from)?,
+                };
+
+                if transaction.nonce != sender.nonce {
+                    return Err(StateError::InvalidNonce);
+                }
+
+                sender.balance = sender
+                    .balance
+                    .checked_sub(cost)
+                    .ok_or(StateError::InsufficientBalance)?;
+                if sender.balance < immature.get(&transaction.from).copied().unwrap_or(0) {
+                    return Err(StateError::ImmatureReward);
+                }
+                sender.nonce += 1;
+                staged.insert(transaction.from.clone(), sender);
+            }
+
+            let mut receiver = match staged.get(&transaction.to) {
+                Some(account) => account.clone(),
+                None => self.get_account(&transaction.to)?,
+            };
+
+            receiver.balance = receiver
+                .balance
+                .checked_add(value)
+                .ok_or(StateError::BalanceOverflow)?;
+            staged.insert(transaction.to.clone(), receiver);
+        }
+
+        let undo = StateUndo {
+            previous_root: self.trie.root(),
+        };
+        let result = staged
+            .iter()
+            .try_for_each(|(address, account)| self.put_account(address, account));
+        if let Err(e) = result {
+            self.discard(undo);
+            return Err(e);
+        }
+
+        Ok(undo)
+    }
+
+    pub fn revert(&mut self, undo: StateUndo) {
+        self.trie.set_root(undo.previous_root);
+    }
+
+    /// Reverts uncommitted changes and drops the nodes they created.
+    fn discard(&mut self, undo: StateUndo) {
+        self.revert(undo);
+        self.trie.discard_pending();
+    }
+}
@@ -1,10 +1,12 @@
+use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
+use crate::chain::transaction::Transaction;
 use crate::cryptography::signature::{get_private_key, sign};
 use crate::utils::conversion::public_key_to_address;
 use crate::utils::conversion::to_hex;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Encode, Decode)]
 pub struct Wallet {
     pub address: Vec<u8>,
     pub public_key: Vec<u8>,
@@ -24,7 +26,6 @@ impl Wallet {
         to_hex(&self.address)
     }
 
-    #[allow(dead_code)] // TODO: this can be used by a contract interpreter
     pub fn sign(&self, message: &[u8]) -> Vec<u8> {
         sign(
             message,
@@ -32,11 +33,15 @@ impl Wallet {
         )
     }
 
-    pub fn send(&self, to: &Wallet, value: u64) -> Vec<u8> {
-        let mut data = Vec::new();
-        data.extend_from_slice(&self.address);
-        data.extend_from_slice(&to.address);
-        data.extend_from_slice(&value.to_be_bytes());
-        self.sign(&data)
+    #[allow(dead_code)]
+    pub fn send(
+        &self,
+        to: &Wallet,
+        value: u64,
+        fee: u64,
+        nonce: u64,
+        chain_id: u64,
+    ) -> Transaction {
+        Transaction::signed(self, &to.address, vec![value], fee, nonce, chain_id)
     }
 }
@@ -1,12 +1,22 @@
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    impl_borrow_decode, Decode, Encode,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::chain::block_manager::BlockManager;
-use crate::chain::blockchain::Blockchain;
+use crate::chain::blockchain::{BlockOutcome, Blockchain};
+use crate::chain::codec;
+use crate::chain::target::U256;
 use crate::chain::transaction::Transaction;
-use crate::cryptography::hash::transform;
-use crate::utils::conversion::to_hex;
+use crate::cryptography::hash::{Hasher, Keccak256};
+use crate::storage::tree::Tree;
 use crate::utils::time::get_timestamp;
 
+const BLOCK_VERSION: u32 = 1;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BlockStatus {
     Unfinalized,
@@ -14,173 +24,260 @@ pub enum BlockStatus {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
-    pub transactions: Vec<Transaction>,
-    pub previous_hash: Vec<u8>,
-    pub hash: Vec<u8>,
-    pub nonce: u64,
+pub struct TransactionProof {
+    pub transaction_hash: [u8; 32],
+    pub index: usize,
+    pub total_transactions: usize,
+    pub proof: Vec<u8>,
+}
+
+impl TransactionProof {
+    pub fn verify(&self, transactions_root: &[u8; 32]) -> bool {
+        Tree::verify_proof_with_root(
+            *transactions_root,
+            &[Block::transaction_leaf(&self.transaction_hash)],
+            &[self.index],
+            &self.proof,
+            self.total_transactions,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub previous_hash: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub state_root: [u8; 32],
     pub timestamp: u64,
-    pub status: BlockStatus,
+    /// Proof-of-work target in compact form, see [`U256::from_compact`].
+    pub bits: u32,
+    pub nonce: u64,
     pub height: u64,
 }
 
-impl Block {
-    pub fn new(transactions: Vec<Transaction>, previous_hash: Vec<u8>, height: u64) -> Self {
-        let block_data = Self::build_block_data(&transactions, &previous_hash, 0, get_timestamp());
+impl BlockHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        codec::encode(self)
+    }
 
-        Self {
-            transactions,
+    pub fn hash(&self) -> [u8; 32] {
+        Keccak256::hash(&self.to_bytes())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Encode, Decode)]
+pub struct BlockBody {
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub body: BlockBody,
+    pub hash: [u8; 32],
+    pub status: BlockStatus,
+}
+
+/// Only the header and body are consensus data. The hash is derived from the
+/// header and the status is local to this node, so neither is encoded.
+impl Encode for Block {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.header.encode(encoder)?;
+        self.body.encode(encoder)
+    }
+}
+
+/// Decoded blocks were finalized by whoever encoded them.
+impl<Context> Decode<Context> for Block {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode(decoder)?;
+        let body = BlockBody::decode(decoder)?;
+        Ok(Self {
+            hash: header.hash(),
+            header,
+            body,
+            status: BlockStatus::Finalized,
+        })
+    }
+}
+
+impl_borrow_decode!(Block);
+
+impl Block {
+    pub fn new(transactions: Vec<Transaction>, previous_hash: [u8; 32], height: u64) -> Self {
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
             previous_hash,
-            hash: transform(&block_data).into_bytes(),
-            nonce: 0,
+            transactions_root: Self::compute_transactions_root(&transactions),
+            state_root: [0u8; 32],
             timestamp: get_timestamp(),
-            status: BlockStatus::Unfinalized,
+            bits: 0,
+            nonce: 0,
             height,
+        };
+
+        Self {
+            hash: header.hash(),
+            header,
+            body: BlockBody { transactions },
+            status: BlockStatus::Unfinalized,
         }
     }
 
-    fn build_block_data(
-        transactions: &Vec<Transaction>,
-        previous_hash: &Vec<u8>,
-        nonce: u64,
-        timestamp: u64,
-    ) -> String {
-        let mut block_data = String::new();
+    pub fn genesis(timestamp: u64, bits: u32, state_root: [u8; 32]) -> Self {
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            previous_hash: [0u8; 32],
+            transactions_root: Self::compute_transactions_root(&[]),
+            state_root,
+            timestamp,
+            bits,
+            nonce: 0,
+            height: 0,
+        };
 
-        block_data.push_str(&to_hex(previous_hash));
+        Self {
+            hash: header.hash(),
+            header,
+            body: BlockBody::default(),
+            status: BlockStatus::Finalized,
+        }
+    }
 
-        let transactions_str = transactions
-            .iter()
-            .map(|tx| tx.to_string())
-            .collect::<String>();
+    /// Size of the canonical encoding, checked against the block size limit.
+    pub fn size(&self) -> usize {
+        codec::encode(self).len()
+    }
 
-        let merkle_root = transform(&transactions_str);
-        block_data.push_str(&merkle_root);
-        block_data.push_str(&nonce.to_string());
-        block_data.push_str(&timestamp.to_string());
+    fn transaction_leaf(transaction_hash: &[u8; 32]) -> [u8; 32] {
+        Keccak256::hash(transaction_hash)
+    }
 
-        block_data
+    fn transaction_tree(transactions: &[Transaction]) -> Tree {
+        let leaves: Vec<[u8; 32]> = transactions
+            .iter()
+            .map(|tx| Self::transaction_leaf(&tx.hash))
+            .collect();
+        Tree::from_leaves("transactions".to_string(), &leaves)
     }
 
-    fn meets_difficulty(&self, hash: &Vec<u8>, target_bits: u64) -> bool {
-        let mut leading_zeros = 0;
+    pub fn compute_transactions_root(transactions: &[Transaction]) -> [u8; 32] {
+        Self::transaction_tree(transactions)
+            .get_root()
+            .unwrap_or_default()
+    }
 
-        for byte in hash {
-            if *byte == 0 {
-                leading_zeros += 8;
-                continue;
-            }
+    pub fn has_valid_transactions_root(&self) -> bool {
+        self.header.transactions_root == Self::compute_transactions_root(&self.body.transactions)
+    }
 
-            let mut mask = 0x80;
-            while mask > 0 && (*byte & mask) == 0 {
-                leading_zeros += 1;
-                mask >>= 1;
-            }
-            break;
-        }
+    pub fn transaction_proof(&self, transaction_hash: &[u8; 32]) -> Option<TransactionProof> {
+        let index = self
+            .body
+            .transactions
+            .iter()
+            .position(|tx| tx.hash == *transaction_hash)?;
+        let tree = Self::transaction_tree(&self.body.transactions);
 
-        leading_zeros >= target_bits
+        Some(TransactionProof {
+            transaction_hash: *transaction_hash,
+            index,
+            total_transactions: self.body.transactions.len(),
+            proof: tree.generate_proof_bytes(&[index]),
+        })
     }
 
-    fn get_difficulty_target(&self, chain: &Blockchain) -> u64 {
-        const INITIAL_DIFFICULTY_BITS: u64 = 16;
-        const TARGET_BLOCK_TIME: u64 = 600; // 10 minutes in seconds
-        const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 2016; // ~2 weeks of blocks
-
-        if self.height == 0 {
-            return INITIAL_DIFFICULTY_BITS;
-        }
+    /// Whether the hash, read as a big-endian integer, is at or below the
+    /// target encoded in `bits`. Invalid targets are never met.
+    fn meets_target(hash: &[u8; 32], bits: u32) -> bool {
+        U256::from_compact(bits).is_some_and(|target| U256::from_be_bytes(hash) <= target)
+    }
 
-        if self.height % DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
-            return chain.current_difficulty_bits;
+    pub fn mine(
+        &mut self,
+        blockchain: &mut Blockchain,
+        block_manager: &mut BlockManager,
+    ) -> Option<BlockOutcome> {
+        println!("Mining block {}", self.header.height);
+        let max_attempts = 1_000_000;
+        // The state root is computed on top of the tip, so a template whose
+        // parent was overtaken since it was built would commit to the wrong
+        // state.
+        if self.header.previous_hash != blockchain.tip().hash {
+            eprintln!(
+                "Cannot mine block {}: its parent is no longer the tip",
+                self.header.height
+            );
+            return None;
         }
-
-        let adjustment_start_height = self.height - DIFFICULTY_ADJUSTMENT_INTERVAL;
-        let adjustment_start_block = chain
-            .get_block_by_height(adjustment_start_height)
-            .expect("Failed to get adjustment start block");
-
-        let time_diff = self.timestamp - adjustment_start_block.timestamp;
-        let expected_time = TARGET_BLOCK_TIME * DIFFICULTY_ADJUSTMENT_INTERVAL;
-        let mut new_difficulty = chain.current_difficulty_bits;
-
-        if time_diff < expected_time / 4 {
-            new_difficulty += 1;
-        } else if time_diff > expected_time * 4 {
-            new_difficulty = new_difficulty.saturating_sub(1);
-        } else {
-            let adjustment = (expected_time as f64) / (time_diff as f64);
-            let adjustment = adjustment.max(0.25).min(4.0);
-            if adjustment > 1.0 {
-                new_difficulty += 1;
-            } else if adjustment < 1.0 {
-                new_difficulty = new_difficulty.saturating_sub(1);
+        self.header.bits = match blockchain.next_bits(&self.header.previous_hash) {
+            Some(bits) => bits,
+            None => {
+                eprintln!("Cannot mine block {}: unknown parent", self.header.height);
+                return None;
             }
+        };
+        if let Some(median) = blockchain.median_time_past(&self.header.previous_hash) {
+            self.header.timestamp = self.header.timestamp.max(median + 1);
         }
-
-        new_difficulty
-    }
-
-    pub fn mine(&mut self, blockchain: &mut Blockchain, block_manager: &mut BlockManager) -> bool {
-        println!("Mining block {}", self.height);
-        let target_bits = self.get_difficulty_target(blockchain);
-        let max_attempts = 1_000_000;
+        self.header.transactions_root = Self::compute_transactions_root(&self.body.transactions);
+        self.header.state_root = match blockchain.compute_state_root(&self.body.transactions) {
+            Ok(state_root) => state_root,
+            Err(e) => {
+                eprintln!("Cannot mine block {}: {}", self.header.height, e);
+                return None;
+            }
+        };
 
         for _ in 0..max_attempts {
-            self.nonce += 1;
-            let block_data = Self::build_block_data(
-                &self.transactions,
-                &self.previous_hash,
-                self.nonce,
-                self.timestamp,
-            );
+            self.header.nonce += 1;
 
-            let hash = transform(&block_data).into_bytes();
-            if self.meets_difficulty(&hash, target_bits) {
+            let hash = self.header.hash();
+            if Self::meets_target(&hash, self.header.bits) {
                 self.hash = hash;
-                self.finalize(self.clone(), blockchain, block_manager);
-                return true;
+                return self.finalize(blockchain, block_manager);
             }
         }
 
-        false
+        None
     }
 
+    #[allow(dead_code)]
     pub fn add_transaction(&mut self, transaction: Transaction) -> bool {
         if matches!(self.status, BlockStatus::Finalized) {
             return false;
         }
 
-        self.transactions.push(transaction);
+        self.body.transactions.push(transaction);
+        self.header.transactions_root = Self::compute_transactions_root(&self.body.transactions);
         true
     }
 
     fn finalize(
         &mut self,
-        copy: Block,
         blockchain: &mut Blockchain,
-        blocK_manager: &mut BlockManager,
-    ) {
+        block_manager: &mut BlockManager,
+    ) -> Option<BlockOutcome> {
         self.status = BlockStatus::Finalized;
-        match blockchain.add_block(copy) {
-            Ok(_) => blocK_manager.remove_unfinalized_block(self.height),
-            Err(e) => println!("{:?}", e),
+        match blockchain.add_block(self.clone()) {
+            Ok(outcome) => {
+                block_manager.remove_unfinalized_block(self.header.height);
+                Some(outcome)
+            }
+            Err(e) => {
+                println!("{:?}", e);
+                None
+            }
         }
     }
 
-    pub fn verify(&self, target_bits: u64) -> bool {
-        let block_data = Self::build_block_data(
-            &self.transactions,
-            &self.previous_hash,
-            self.nonce,
-            self.timestamp,
-        );
-
-        let hash = transform(&block_data).into_bytes();
+    pub fn verify(&self) -> bool {
+        let hash = self.header.hash();
         if hash != self.hash {
             return false;
         }
 
-        self.meets_difficulty(&hash, target_bits)
+        Self::meets_target(&hash, self.header.bits)
     }
 }
@@ -2,59 +2,48 @@ use std::time::{Duration, Instant};
 
 use hashlink::LinkedHashMap;
 
-use crate::chain::{
-    block::{Block, BlockStatus},
-    blockchain::Blockchain,
-    transaction::Transaction,
-};
+use crate::chain::{block::Block, blockchain::Blockchain, transaction::Transaction};
+use crate::utils::{conversion::from_hex, env::get_miner_address};
 
 #[derive(Debug)]
 pub struct BlockManager {
-    pending_transactions: Vec<Transaction>,
     last_block_time: Instant,
     block_interval: Duration,
     unfinalized_blocks: LinkedHashMap<u64, Block>,
+    miner_address: Option<Vec<u8>>,
 }
 
 impl BlockManager {
-    pub fn new(block_interval_secs: u64) -> Self {
+    pub fn new(block_interval_secs: u64, miner_address: Option<Vec<u8>>) -> Self {
         Self {
-            pending_transactions: Vec::new(),
             last_block_time: Instant::now(),
             block_interval: Duration::from_secs(block_interval_secs),
             unfinalized_blocks: LinkedHashMap::new(),
+            miner_address,
         }
     }
 
-    pub fn add_transaction(&mut self, blockchain: &mut Blockchain, transaction: Transaction) {
-        self.pending_transactions.push(transaction.clone());
-
-        let last_block = self.unfinalized_blocks.values_mut().last();
-        match last_block {
-            Some(last_block) => {
-                last_block.add_transaction(transaction);
-            }
-            None => {
-                let mut last_block = blockchain.blocks.last().unwrap().clone();
-                if matches!(last_block.status, BlockStatus::Finalized) {
-                    let new_block = self.process_block_creation(blockchain);
-                    match new_block {
-                        Some(mut new_block) => {
-                            new_block.add_transaction(transaction);
-                        }
-                        None => {}
-                    }
-                } else {
-                    last_block.add_transaction(transaction);
+    /// Creates a manager paying block rewards to `MINER_ADDRESS`. Without
+    /// it the node still follows the chain but assembles no blocks.
+    pub fn from_env(block_interval_secs: u64) -> Self {
+        let miner_address = match get_miner_address() {
+            Some(address) => match from_hex(&address) {
+                Ok(bytes) if !bytes.is_empty() => {
+                    println!("Mining rewards are paid to {}", address);
+                    Some(bytes)
                 }
+                _ => panic!("Invalid MINER_ADDRESS: {}", address),
+            },
+            None => {
+                println!("MINER_ADDRESS not set, this node will not mine blocks");
+                None
             }
-        }
+        };
+        Self::new(block_interval_secs, miner_address)
     }
 
     pub fn process_block_creation(&mut self, blockchain: &mut Blockchain) -> Option<Block> {
-        if self.pending_transactions.is_empty() {
-            return None;
-        }
+        let miner_address = self.miner_address.clone()?;
 
         if Instant::now().duration_since(self.last_block_time) < self.block_interval {
             return None;
@@ -62,11 +51,36 @@ impl BlockManager {
 
         let height = blockchain.blocks.len() as u64;
         let previous_hash = match blockchain.blocks.last() {
-            Some(last_block) => last_block.hash.clone(),
-            None => blockchain.genesis_hash.clone(),
+            Some(last_block) => last_block.hash,
+            None => blockchain.genesis_hash,
         };
 
-        let transactions = std::mem::take(&mut self.pending_transactions);
+        let subsidy = blockchain.rewards.subsidy(height);
+        let base_size = Block::new(
+            vec![Transaction::coinbase(
+                &miner_address,
+                subsidy,
+                height,
+                blockchain.chain_id,
+            )],
+            previous_hash,
+            height,
+        )
+        .size();
+        let selected = blockchain
+            .mempool
+            .select(blockchain.max_block_size.saturating_sub(base_size));
+        let fees = selected
+            .iter()
+            .fold(0u64, |fees, tx| fees.saturating_add(tx.fee));
+
+        let coinbase = Transaction::coinbase(
+            &miner_address,
+            subsidy.saturating_add(fees),
+            height,
+            blockchain.chain_id,
+        );
+        let transactions = std::iter::once(coinbase).chain(selected).collect();
         let new_block = Block::new(transactions, previous_hash, height);
         let block_copy = new_block.clone();
         self.unfinalized_blocks.insert(height, block_copy);
@@ -76,6 +90,7 @@ impl BlockManager {
         Some(new_block)
     }
 
+    #[allow(dead_code)]
     pub fn get_unfinalized_block(&self, height: u64) -> Option<&Block> {
         self.unfinalized_blocks.get(&height)
     }
@@ -1,72 +1,801 @@
-use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::Arc;
 use tokio;
 
+use crate::account::state::{Account, AccountState, StateError, StateUndo};
 use crate::account::wallet::Wallet;
-use crate::chain::block::{Block, BlockStatus};
-use crate::client::network::SharedState;
-use crate::storage::ledger::LedgerValue;
+use crate::chain::block::{Block, BlockHeader, BlockStatus};
+use crate::chain::difficulty::{DifficultyAlgorithm, DifficultyUpdate, HeaderSource};
+use crate::chain::mempool::{Mempool, MempoolError};
+use crate::chain::orphan_pool::OrphanPool;
+use crate::chain::reward::RewardSchedule;
+use crate::chain::spec::ChainSpec;
+use crate::chain::target::U256;
+use crate::chain::transaction::Transaction;
+use crate::client::network::{block_message, broadcast_to_peers, SharedState};
+use crate::storage::backend::Storage;
+use crate::storage::chain_store::ChainStore;
+use crate::utils::conversion::to_hex;
+use crate::utils::time::get_timestamp;
 
 use super::block_manager::BlockManager;
 
 #[derive(Debug)]
 pub enum BlockchainError {
     UnfinalizedBlock,
+    KnownBlock,
     InvalidPreviousHash,
+    OrphanBlock([u8; 32]),
     InvalidBlockHeight,
     InvalidProofOfWork,
+    InvalidDifficulty,
+    TimestampTooOld,
+    TimestampTooFarAhead,
+    InvalidTransactionsRoot,
+    InvalidStateRoot,
+    InvalidSignature,
+    WrongChain,
+    InvalidCoinbase,
+    BlockTooLarge,
+    InvalidState(StateError),
+    GenesisMismatch,
+    MissingBlock(u64),
+    MissingTip,
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockchainError::UnfinalizedBlock => write!(f, "block is not finalized"),
+            BlockchainError::KnownBlock => write!(f, "block is already known"),
+            BlockchainError::InvalidPreviousHash => write!(f, "invalid previous hash"),
+            BlockchainError::OrphanBlock(missing) => {
+                write!(f, "orphan block, missing ancestor {}", to_hex(missing))
+            }
+            BlockchainError::InvalidBlockHeight => write!(f, "invalid block height"),
+            BlockchainError::InvalidProofOfWork => write!(f, "invalid proof of work"),
+            BlockchainError::InvalidDifficulty => {
+                write!(f, "block target does not match the difficulty algorithm")
+            }
+            BlockchainError::TimestampTooOld => write!(
+                f,
+                "timestamp is not after the median of the last {} blocks",
+                MEDIAN_TIME_BLOCKS
+            ),
+            BlockchainError::TimestampTooFarAhead => write!(
+                f,
+                "timestamp is more than {} seconds ahead of local time",
+                MAX_FUTURE_DRIFT_SECS
+            ),
+            BlockchainError::InvalidTransactionsRoot => write!(f, "invalid transactions root"),
+            BlockchainError::InvalidStateRoot => write!(f, "invalid state root"),
+            BlockchainError::InvalidSignature => write!(f, "invalid transaction signature"),
+            BlockchainError::WrongChain => {
+                write!(f, "block contains a transaction signed for another chain")
+            }
+            BlockchainError::InvalidCoinbase => {
+                write!(
+                    f,
+                    "block must start with the one coinbase paying its reward"
+                )
+            }
+            BlockchainError::BlockTooLarge => write!(f, "block exceeds the maximum size"),
+            BlockchainError::InvalidState(e) => write!(f, "invalid state transition: {}", e),
+            BlockchainError::GenesisMismatch => {
+                write!(f, "stored genesis does not match the chain spec")
+            }
+            BlockchainError::MissingBlock(height) => {
+                write!(f, "stored block at height {} is missing", height)
+            }
+            BlockchainError::MissingTip => {
+                write!(f, "stored chain tip does not point to a stored block")
+            }
+        }
+    }
+}
+
+const MAX_ORPHAN_BLOCKS: usize = 128;
+const MAX_ORPHAN_AGE_SECS: u64 = 600;
+const MAX_MEMPOOL_TRANSACTIONS: usize = 5_000;
+const MAX_MEMPOOL_SIZE: usize = 5_000_000;
+/// Ancestors whose median timestamp a block has to exceed.
+const MEDIAN_TIME_BLOCKS: u64 = 11;
+/// How far a block's timestamp may run ahead of the local clock.
+const MAX_FUTURE_DRIFT_SECS: u64 = 2 * 60 * 60;
+
+#[derive(Debug)]
+pub enum BlockOutcome {
+    Extended {
+        block: Block,
+        difficulty_update: Option<DifficultyUpdate>,
+    },
+    SideBranch,
+    Reorganized {
+        disconnected: Vec<Block>,
+        connected: Vec<Block>,
+        difficulty_updates: Vec<DifficultyUpdate>,
+    },
+}
+
+impl BlockOutcome {
+    /// Blocks the outcome added to the active chain, lowest first.
+    pub fn connected_blocks(&self) -> &[Block] {
+        match self {
+            BlockOutcome::Extended { block, .. } => std::slice::from_ref(block),
+            BlockOutcome::SideBranch => &[],
+            BlockOutcome::Reorganized { connected, .. } => connected,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainEntry {
+    pub block: Block,
+    pub cumulative_work: U256,
+}
+
+/// Headers along a branch: blocks above the fork point come from the block
+/// index, everything at or below it from the active chain.
+struct BranchHeaders<'a> {
+    main_chain: &'a [Block],
+    fork_height: u64,
+    side: Vec<&'a BlockHeader>,
+}
+
+impl HeaderSource for BranchHeaders<'_> {
+    fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        if height <= self.fork_height {
+            return self
+                .main_chain
+                .get(height as usize)
+                .map(|block| &block.header);
+        }
+        self.side
+            .get((height - self.fork_height - 1) as usize)
+            .copied()
+    }
 }
 
 #[allow(dead_code)]
 pub struct Blockchain {
     pub blocks: Vec<Block>,
+    pub block_index: HashMap<[u8; 32], ChainEntry>,
+    undo_logs: HashMap<[u8; 32], StateUndo>,
+    pub orphans: OrphanPool,
+    pub mempool: Mempool,
     pub accounts: Vec<Wallet>,
-    pub current_difficulty_bits: u64,
-    pub genesis_hash: Vec<u8>,
+    pub state: AccountState,
+    /// Compact target of the active tip.
+    pub current_bits: u32,
+    /// Easiest target a block may claim, taken from the chain spec.
+    pub pow_limit: U256,
+    difficulty: Box<dyn DifficultyAlgorithm>,
+    pub rewards: RewardSchedule,
+    pub max_block_size: usize,
+    pub genesis_hash: [u8; 32],
+    pub chain_id: u64,
+    store: Option<ChainStore>,
 }
 
 impl Blockchain {
-    pub fn new(current_difficulty_bits: u64) -> Self {
-        let genesis_block = Block::new(vec![], vec![], 0);
+    pub fn new(spec: &ChainSpec, state_storage: Box<dyn Storage>) -> Self {
+        let allocations = spec
+            .allocation_balances()
+            .expect("Chain spec allocations must be valid");
+        let state = AccountState::from_allocations(state_storage, &allocations)
+            .unwrap_or_else(|e| panic!("Failed to build genesis state: {}", e));
+        let genesis_block = spec.genesis_block(state.root());
+        println!(
+            "Genesis block {} for chain id {}",
+            to_hex(&genesis_block.hash),
+            spec.chain_id
+        );
+
+        let genesis_entry = ChainEntry {
+            block: genesis_block.clone(),
+            cumulative_work: Self::block_work(genesis_block.header.bits),
+        };
 
         Self {
-            genesis_hash: genesis_block.hash.clone(),
-            blocks: vec![genesis_block],
+            genesis_hash: genesis_block.hash,
+            blocks: vec![genesis_block.clone()],
+            block_index: HashMap::from([(genesis_block.hash, genesis_entry)]),
+            undo_logs: HashMap::new(),
+            orphans: OrphanPool::new(MAX_ORPHAN_BLOCKS, MAX_ORPHAN_AGE_SECS),
+            mempool: Mempool::new(MAX_MEMPOOL_TRANSACTIONS, MAX_MEMPOOL_SIZE),
             accounts: vec![],
-            current_difficulty_bits,
+            state,
+            current_bits: spec.initial_bits,
+            pow_limit: U256::from_compact(spec.initial_bits)
+                .expect("Chain spec initial bits must be a valid target"),
+            difficulty: spec.difficulty.build(spec.target_block_time),
+            rewards: spec.rewards,
+            max_block_size: spec.max_block_size as usize,
+            chain_id: spec.chain_id,
+            store: None,
+        }
+    }
+
+    /// Rebuilds the chain from the blocks persisted in `store`, replaying the
+    /// main chain through `add_block` so every stored block is revalidated.
+    pub fn load(
+        spec: &ChainSpec,
+        mut store: ChainStore,
+        state_storage: Box<dyn Storage>,
+    ) -> Result<Self, BlockchainError> {
+        let mut blockchain = Self::new(spec, state_storage);
+
+        if let Some(stored_genesis) = store.get_hash_by_height(0) {
+            if stored_genesis != blockchain.genesis_hash {
+                return Err(BlockchainError::GenesisMismatch);
+            }
+        }
+
+        let tip_height = match store.get_tip() {
+            Some(hash) => {
+                store
+                    .get_block(&hash)
+                    .ok_or(BlockchainError::MissingTip)?
+                    .header
+                    .height
+            }
+            None => 0,
+        };
+
+        for height in 1..=tip_height {
+            let block = store
+                .get_hash_by_height(height)
+                .and_then(|hash| store.get_block(&hash))
+                .ok_or(BlockchainError::MissingBlock(height))?;
+            blockchain.add_block(block)?;
+        }
+
+        println!("Reloaded {} blocks from disk", tip_height);
+
+        blockchain.store = Some(store);
+        let genesis = blockchain.blocks[0].clone();
+        if let Some(store) = blockchain.store.as_mut() {
+            if let Err(e) = store.put_block(&genesis) {
+                eprintln!("Failed to persist genesis block: {}", e);
+            }
+        }
+        if tip_height == 0 {
+            blockchain.persist_tip();
         }
+
+        Ok(blockchain)
+    }
+
+    fn block_work(bits: u32) -> U256 {
+        U256::from_compact(bits).map_or(U256::ZERO, |target| target.work())
+    }
+
+    fn within_pow_limit(&self, block: &Block) -> bool {
+        U256::from_compact(block.header.bits).is_some_and(|target| target <= self.pow_limit)
+    }
+
+    /// Headers of the branch ending at `tip_hash`, which need not be the
+    /// active chain.
+    fn branch_headers(&self, tip_hash: &[u8; 32]) -> Option<BranchHeaders<'_>> {
+        let mut side = Vec::new();
+        let mut cursor = *tip_hash;
+        let fork_height = loop {
+            let entry = self.block_index.get(&cursor)?;
+            if self.is_on_main_chain(&entry.block) {
+                break entry.block.header.height;
+            }
+            side.push(&entry.block.header);
+            cursor = entry.block.header.previous_hash;
+        };
+        side.reverse();
+
+        Some(BranchHeaders {
+            main_chain: &self.blocks,
+            fork_height,
+            side,
+        })
+    }
+
+    /// The target change `block` makes over its parent, if any.
+    fn difficulty_update(&self, block: &Block) -> Option<DifficultyUpdate> {
+        let parent = self.block_index.get(&block.header.previous_hash)?;
+        DifficultyUpdate::between(&parent.block.header, &block.header)
+    }
+
+    /// Target changes along the active chain, lowest first.
+    pub fn difficulty_updates(&self) -> Vec<DifficultyUpdate> {
+        self.blocks
+            .iter()
+            .skip(1)
+            .filter_map(|block| self.difficulty_update(block))
+            .collect()
+    }
+
+    /// Compact target the difficulty algorithm requires of a block extending
+    /// `parent_hash`, or `None` when the parent is unknown.
+    pub fn next_bits(&self, parent_hash: &[u8; 32]) -> Option<u32> {
+        let parent = &self.block_index.get(parent_hash)?.block.header;
+        let headers = self.branch_headers(parent_hash)?;
+        Some(self.difficulty.next_bits(parent, &headers, self.pow_limit))
+    }
+
+    /// Median timestamp of `parent_hash` and up to ten of its ancestors on
+    /// the same branch, or `None` when the parent is unknown.
+    pub fn median_time_past(&self, parent_hash: &[u8; 32]) -> Option<u64> {
+        let parent = &self.block_index.get(parent_hash)?.block.header;
+        let headers = self.branch_headers(parent_hash)?;
+        let first = (parent.height + 1).saturating_sub(MEDIAN_TIME_BLOCKS);
+        let mut timestamps: Vec<u64> = (first..=parent.height)
+            .filter_map(|height| headers.header_at(height))
+            .map(|header| header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    pub fn tip(&self) -> &Block {
+        self.blocks.last().expect("Chain always contains genesis")
+    }
+
+    fn tip_work(&self) -> U256 {
+        self.block_index
+            .get(&self.tip().hash)
+            .map_or(U256::ZERO, |entry| entry.cumulative_work)
+    }
+
+    fn is_on_main_chain(&self, block: &Block) -> bool {
+        self.get_block_by_height(block.header.height)
+            .is_some_and(|main_block| main_block.hash == block.hash)
     }
 
-    pub fn add_block(&mut self, block: Block) -> Result<(), BlockchainError> {
+    /// Outcome that brings a record of the chain holding `recorded` up to
+    /// date with the active chain: recorded blocks that left the active
+    /// chain are disconnected, and the active chain is connected from the
+    /// lowest height where the record diverges or stops.
+    pub fn catch_up_outcome<'a>(
+        &self,
+        recorded: impl IntoIterator<Item = &'a Block>,
+    ) -> BlockOutcome {
+        let (main, mut disconnected): (Vec<&Block>, Vec<&Block>) = recorded
+            .into_iter()
+            .partition(|block| self.is_on_main_chain(block));
+        disconnected.sort_by_key(|block| std::cmp::Reverse(block.header.height));
+
+        let next_recorded = main
+            .iter()
+            .map(|block| block.header.height)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let start = disconnected
+            .iter()
+            .map(|block| block.header.height)
+            .fold(next_recorded, u64::min)
+            .max(1);
+        let connected: Vec<Block> = self.blocks.iter().skip(start as usize).cloned().collect();
+        let difficulty_updates = connected
+            .iter()
+            .filter_map(|block| self.difficulty_update(block))
+            .collect();
+
+        BlockOutcome::Reorganized {
+            disconnected: disconnected.into_iter().cloned().collect(),
+            connected,
+            difficulty_updates,
+        }
+    }
+
+    /// Validates a block against its parent and files it in the block tree.
+    /// Blocks extending the tip are connected right away; blocks on another
+    /// branch are kept aside until their branch carries more cumulative work
+    /// than the active chain, at which point the chain is reorganized. Blocks
+    /// whose parent is unknown go to the orphan pool.
+    pub fn add_block(&mut self, block: Block) -> Result<BlockOutcome, BlockchainError> {
         if matches!(block.status, BlockStatus::Unfinalized) {
             return Err(BlockchainError::UnfinalizedBlock);
         }
 
-        let expected_prev_hash = match self.blocks.last() {
-            Some(last_block) => &last_block.hash,
-            None => &self.genesis_hash,
-        };
+        if self.block_index.contains_key(&block.hash) {
+            return Err(BlockchainError::KnownBlock);
+        }
+
+        if !self.within_pow_limit(&block) || !block.verify() {
+            return Err(BlockchainError::InvalidProofOfWork);
+        }
+
+        if !block.has_valid_transactions_root() {
+            return Err(BlockchainError::InvalidTransactionsRoot);
+        }
+
+        if block.size() > self.max_block_size {
+            return Err(BlockchainError::BlockTooLarge);
+        }
 
-        if block.previous_hash != *expected_prev_hash {
-            return Err(BlockchainError::InvalidPreviousHash);
+        if !self.has_valid_coinbase(&block) {
+            return Err(BlockchainError::InvalidCoinbase);
         }
 
-        if block.height != self.blocks.len() as u64 {
+        if block
+            .body
+            .transactions
+            .iter()
+            .any(|tx| tx.chain_id != self.chain_id)
+        {
+            return Err(BlockchainError::WrongChain);
+        }
+
+        if !block
+            .body
+            .transactions
+            .iter()
+            .skip(1)
+            .all(|tx| tx.verify_signature())
+        {
+            return Err(BlockchainError::InvalidSignature);
+        }
+
+        let Some(parent) = self.block_index.get(&block.header.previous_hash) else {
+            if block.header.height == 0 {
+                return Err(BlockchainError::InvalidPreviousHash);
+            }
+            let missing = self.orphans.insert(block);
+            return Err(BlockchainError::OrphanBlock(missing));
+        };
+
+        if block.header.height != parent.block.header.height + 1 {
             return Err(BlockchainError::InvalidBlockHeight);
         }
 
-        if !block.verify(self.current_difficulty_bits) {
-            return Err(BlockchainError::InvalidProofOfWork);
+        if self
+            .median_time_past(&block.header.previous_hash)
+            .is_some_and(|median| block.header.timestamp <= median)
+        {
+            return Err(BlockchainError::TimestampTooOld);
+        }
+
+        if block.header.timestamp > get_timestamp().saturating_add(MAX_FUTURE_DRIFT_SECS) {
+            return Err(BlockchainError::TimestampTooFarAhead);
+        }
+
+        if self.next_bits(&block.header.previous_hash) != Some(block.header.bits) {
+            return Err(BlockchainError::InvalidDifficulty);
+        }
+
+        let entry = ChainEntry {
+            cumulative_work: parent
+                .cumulative_work
+                .saturating_add(Self::block_work(block.header.bits)),
+            block: block.clone(),
+        };
+
+        if block.header.previous_hash == self.tip().hash {
+            self.connect_block(&block)?;
+            self.index_block(entry);
+            self.refresh_mempool(std::slice::from_ref(&block), &[]);
+            return Ok(BlockOutcome::Extended {
+                difficulty_update: self.difficulty_update(&block),
+                block,
+            });
+        }
+
+        let cumulative_work = entry.cumulative_work;
+        self.index_block(entry);
+
+        if cumulative_work <= self.tip_work() {
+            return Ok(BlockOutcome::SideBranch);
+        }
+
+        self.reorganize(&block.hash)
+    }
+
+    /// Connects every pooled orphan that descends from `parent_hash`, returning
+    /// the outcome of each block that was accepted.
+    pub fn connect_orphans(&mut self, parent_hash: &[u8; 32]) -> Vec<BlockOutcome> {
+        let mut outcomes = Vec::new();
+        let mut parents = vec![*parent_hash];
+
+        while let Some(parent) = parents.pop() {
+            for orphan in self.orphans.take_children(&parent) {
+                let hash = orphan.hash;
+                let height = orphan.header.height;
+                match self.add_block(orphan) {
+                    Ok(outcome) => {
+                        println!("Connected orphan block {}", height);
+                        outcomes.push(outcome);
+                        parents.push(hash);
+                    }
+                    Err(e) => eprintln!("Dropping orphan block {}: {}", height, e),
+                }
+            }
         }
 
-        self.blocks.push(block);
+        outcomes
+    }
+
+    /// The first transaction must be a coinbase paying the subsidy of the
+    /// block's height plus the fees of the other transactions, and it must
+    /// be the only one.
+    fn has_valid_coinbase(&self, block: &Block) -> bool {
+        let Some((coinbase, transactions)) = block.body.transactions.split_first() else {
+            return false;
+        };
+
+        let Some(reward) = transactions
+            .iter()
+            .try_fold(self.rewards.subsidy(block.header.height), |reward, tx| {
+                reward.checked_add(tx.fee)
+            })
+        else {
+            return false;
+        };
+
+        coinbase.is_valid_coinbase(block.header.height, reward)
+            && !transactions.iter().any(|tx| tx.is_coinbase())
+    }
+
+    /// Coinbase rewards per address that a block extending the tip with
+    /// `transactions` cannot spend yet: those of its own coinbase and of the
+    /// last `maturity - 1` blocks of the active chain.
+    fn immature_rewards(&self, transactions: &[Transaction]) -> HashMap<Vec<u8>, u64> {
+        let recent = self
+            .blocks
+            .iter()
+            .rev()
+            .take(self.rewards.maturity.saturating_sub(1) as usize)
+            .flat_map(|block| block.body.transactions.first());
+
+        let mut immature = HashMap::new();
+        for coinbase in recent
+            .chain(transactions.first())
+            .filter(|tx| tx.is_coinbase())
+        {
+            let reward: &mut u64 = immature.entry(coinbase.to.clone()).or_default();
+            *reward = reward.saturating_add(coinbase.total_value().unwrap_or(u64::MAX));
+        }
+        immature
+    }
+
+    /// State root the account state would have after applying `transactions`
+    /// on top of the current tip.
+    pub fn compute_state_root(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Result<[u8; 32], BlockchainError> {
+        let immature = self.immature_rewards(transactions);
+        self.state
+            .preview_root(transactions, &immature)
+            .map_err(BlockchainError::InvalidState)
+    }
+
+    fn connect_block(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        let immature = self.immature_rewards(&block.body.transactions);
+        let undo = self
+            .state
+            .apply_transactions(&block.body.transactions, &immature, block.header.state_root)
+            .map_err(|e| match e {
+                StateError::RootMismatch => BlockchainError::InvalidStateRoot,
+                e => BlockchainError::InvalidState(e),
+            })?;
+
+        self.undo_logs.insert(block.hash, undo);
+        self.blocks.push(block.clone());
+        self.current_bits = block.header.bits;
+        self.persist_tip();
         Ok(())
     }
 
+    /// Admits a transaction to the mempool, checked against the account
+    /// state at the tip.
+    pub fn submit_transaction(&mut self, transaction: Transaction) -> Result<(), MempoolError> {
+        if transaction.chain_id != self.chain_id {
+            return Err(MempoolError::WrongChain {
+                expected: self.chain_id,
+            });
+        }
+        let account = self
+            .state
+            .get_account(&transaction.from)
+            .map_err(MempoolError::InvalidState)?;
+        let locked = self
+            .immature_rewards(&[])
+            .get(&transaction.from)
+            .copied()
+            .unwrap_or(0);
+        self.mempool.add(
+            transaction,
+            account.nonce,
+            account.balance.saturating_sub(locked),
+        )
+    }
+
+    /// Updates the mempool for a new tip. Transactions of `disconnected`
+    /// blocks go back in, and pooled transactions of accounts the connected
+    /// or disconnected blocks touched are checked again. Those the chain now
+    /// includes leave the pool, and those that no longer follow their
+    /// sender's nonce or fit their sender's balance are dropped. Other
+    /// senders' queues stay valid.
+    fn refresh_mempool(&mut self, connected: &[Block], disconnected: &[Block]) {
+        if self.mempool.is_empty() && disconnected.is_empty() {
+            return;
+        }
+
+        let immature = self.immature_rewards(&[]);
+        let mut affected: HashSet<&[u8]> = connected
+            .iter()
+            .chain(disconnected)
+            .flat_map(|block| block.body.transactions.iter())
+            .flat_map(|tx| [tx.from.as_slice(), tx.to.as_slice()])
+            .collect();
+        if !disconnected.is_empty() {
+            // Rewards that matured on the old tip may be locked again.
+            affected.extend(immature.keys().map(Vec::as_slice));
+        }
+
+        let mut transactions: Vec<Transaction> = disconnected
+            .iter()
+            .rev()
+            .flat_map(|block| block.body.transactions.iter())
+            .filter(|tx| !tx.is_coinbase())
+            .cloned()
+            .collect();
+        for sender in affected {
+            transactions.extend(self.mempool.remove_sender(sender));
+        }
+        transactions.sort_by_key(|tx| tx.nonce);
+
+        let mined: HashSet<[u8; 32]> = connected
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .map(|tx| tx.hash)
+            .collect();
+        let mut dropped = 0;
+        for transaction in transactions {
+            if mined.contains(&transaction.hash) {
+                continue;
+            }
+            let Ok(account) = self.state.get_account(&transaction.from) else {
+                dropped += 1;
+                continue;
+            };
+            let locked = immature.get(&transaction.from).copied().unwrap_or(0);
+            if self
+                .mempool
+                .insert(
+                    transaction,
+                    account.nonce,
+                    account.balance.saturating_sub(locked),
+                )
+                .is_err()
+            {
+                dropped += 1;
+            }
+        }
+
+        if dropped > 0 {
+            println!(
+                "Dropped {} transactions from the mempool, {} remain",
+                dropped,
+                self.mempool.len()
+            );
+        }
+    }
+
+    fn disconnect_tip(&mut self) -> Block {
+        let block = self.blocks.pop().expect("Cannot disconnect genesis");
+        if let Some(undo) = self.undo_logs.remove(&block.hash) {
+            self.state.revert(undo);
+        }
+        let tip = self.tip().clone();
+        self.current_bits = tip.header.bits;
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.unset_main_chain_block(&block, &tip) {
+                eprintln!(
+                    "Failed to persist disconnection of block {}: {}",
+                    block.header.height, e
+                );
+            }
+        }
+        block
+    }
+
+    /// Files a block in the block index. Blocks on the main chain were
+    /// already stored along with the tip when they were connected.
+    fn index_block(&mut self, entry: ChainEntry) {
+        if !self.is_on_main_chain(&entry.block) {
+            if let Some(store) = self.store.as_mut() {
+                if let Err(e) = store.put_block(&entry.block) {
+                    eprintln!(
+                        "Failed to persist block {}: {}",
+                        entry.block.header.height, e
+                    );
+                }
+            }
+        }
+        self.block_index.insert(entry.block.hash, entry);
+    }
+
+    fn persist_tip(&mut self) {
+        let tip = self.tip().clone();
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.set_main_chain_block(&tip) {
+                eprintln!("Failed to persist chain tip {}: {}", tip.header.height, e);
+            }
+        }
+    }
+
+    fn reorganize(&mut self, new_tip: &[u8; 32]) -> Result<BlockOutcome, BlockchainError> {
+        let mut branch = Vec::new();
+        let mut cursor = *new_tip;
+        loop {
+            let entry = self
+                .block_index
+                .get(&cursor)
+                .ok_or(BlockchainError::InvalidPreviousHash)?;
+            if self.is_on_main_chain(&entry.block) {
+                break;
+            }
+            branch.push(entry.block.clone());
+            cursor = entry.block.header.previous_hash;
+        }
+        branch.reverse();
+
+        let fork_height = branch.first().map_or(0, |block| block.header.height - 1);
+        println!(
+            "Reorganizing chain at height {}: switching to branch of {} blocks",
+            fork_height,
+            branch.len()
+        );
+
+        let mut disconnected = Vec::new();
+        while self.tip().header.height > fork_height {
+            disconnected.push(self.disconnect_tip());
+        }
+
+        let mut connected = Vec::new();
+        for block in &branch {
+            if let Err(e) = self.connect_block(block) {
+                eprintln!(
+                    "Reorg failed at block {}: {}. Restoring previous chain.",
+                    block.header.height, e
+                );
+                for invalid in &branch[connected.len()..] {
+                    self.block_index.remove(&invalid.hash);
+                }
+                while self.tip().header.height > fork_height {
+                    self.disconnect_tip();
+                }
+                for block in disconnected.iter().rev() {
+                    self.connect_block(block)
+                        .expect("Previously connected block must reconnect");
+                }
+                return Err(e);
+            }
+            connected.push(block.clone());
+        }
+
+        let difficulty_updates = connected
+            .iter()
+            .filter_map(|block| self.difficulty_update(block))
+            .collect();
+        self.refresh_mempool(&connected, &disconnected);
+
+        Ok(BlockOutcome::Reorganized {
+            disconnected,
+            connected,
+            difficulty_updates,
+        })
+    }
+
+    #[allow(dead_code)]
     pub fn add_account(&mut self, account: Wallet) {
         self.accounts.push(account);
     }
 
+    pub fn get_account(&mut self, address: &[u8]) -> Result<Account, StateError> {
+        self.state.get_account(address)
+    }
+
     pub fn get_block_by_height(&self, height: u64) -> Option<&Block> {
         self.blocks.get(height as usize)
     }
@@ -87,37 +816,41 @@ impl Blockchain {
                 };
 
                 if let Some(mut block) = maybe_block {
-                    let height = block.height;
+                    let height = block.header.height;
                     println!("Starting mining for block {}", height);
 
                     let bc_clone = Arc::clone(&blockchain);
                     let bm_clone = Arc::clone(&block_manager);
-                    let block_clone = block.clone();
                     let mine_handle = tokio::task::spawn_blocking(move || {
                         let mut guard = tokio::sync::Mutex::blocking_lock_owned(bc_clone);
                         let mut bm = tokio::sync::Mutex::blocking_lock_owned(bm_clone);
-                        block.mine(&mut *guard, &mut *bm)
+                        block
+                            .mine(&mut guard, &mut bm)
+                            .map(|outcome| (block, outcome))
                     });
 
                     match mine_handle.await {
-                        Ok(true) => {
+                        Ok(Some((mined_block, outcome))) => {
                             println!("Mined block {}", height);
                             let mut bm = block_manager.lock().await;
                             bm.remove_unfinalized_block(height);
+                            drop(bm);
 
-                            let parsed_block = &LedgerValue::Blocks(block_clone);
-                            let key = state.ledger.lock().await.get_key(&parsed_block);
-                            let message = state
-                                .ledger
-                                .lock()
-                                .await
-                                .format_entry_value(&key, &parsed_block);
-
-                            if let Err(e) = state.tx.send(Bytes::from(message)) {
-                                eprintln!("Failed to broadcast block: {}", e);
+                            {
+                                let mut ledger = state.ledger.lock().await;
+                                let mut storage = state.storage.lock().await;
+                                if ledger
+                                    .apply_block_outcome(outcome, storage.as_mut())
+                                    .await
+                                    .is_none()
+                                {
+                                    eprintln!("Failed to commit mined block {} to ledger", height);
+                                }
                             }
+
+                            broadcast_to_peers(&state, block_message(&mined_block)).await;
                         }
-                        Ok(false) => eprintln!("Proof‑of‑work failed for {}", height),
+                        Ok(None) => eprintln!("Proof‑of‑work failed for {}", height),
                         Err(e) => eprintln!("Mining thread panicked: {}", e),
                     }
                 }
@@ -0,0 +1,257 @@
+use bincode::{
+    config::{BigEndian, Configuration, Fixint, Limit},
+    Decode, Encode,
+};
+use std::fmt;
+
+/// Version byte written in front of every encoding. Bump it whenever the
+/// layout of an encoded type changes.
+pub const ENCODING_VERSION: u8 = 5;
+
+/// Canonical encoding of transactions, headers, blocks and ledger entries,
+/// used for hashing, signing, storage and the wire. After the version byte,
+/// values are plain bincode with big-endian fixed-width integers: `u32`/`u64`
+/// take 4/8 bytes, a `Vec` is a `u64` length followed by its items, a fixed
+/// array is just its items, and an enum is a `u32` variant index followed by
+/// its fields. Struct fields are written in declaration order, except that a
+/// block is just its header followed by its body.
+///
+/// Golden vectors, for tooling that recomputes hashes:
+///
+/// - the signing payload of a transaction with signer `[0x01; 32]`, from
+///   `[0x01; 20]`, to `[0x02; 20]`, value `[5]`, fee 2, timestamp 1, nonce 7
+///   and chain id 1 is `05` `0000000000000020` `01`×32 `0000000000000014`
+///   `01`×20 `0000000000000014` `02`×20 `0000000000000001` `0000000000000005`
+///   `0000000000000002` `0000000000000001` `0000000000000007`
+///   `0000000000000001`, and its
+///   keccak256 hash is
+///   `f5828227f19c77a58a3c193c6a7f3ed1330aa0e41ad752a608be28dd5d3cc82e`;
+/// - a header with version 1, zeroed previous hash and roots, timestamp 1,
+///   bits `1d00ffff`, nonce 0 and height 0 is `05` `00000001` `00`×96
+///   `0000000000000001` `1d00ffff` `0000000000000000` `0000000000000000`,
+///   and its keccak256 hash is
+///   `9251bc1009fc9c239ff5368491f0bbbef204a08064a3b18b419c703a965afca2`.
+const CONFIG: Configuration<BigEndian, Fixint> = bincode::config::standard()
+    .with_big_endian()
+    .with_fixed_int_encoding();
+
+/// Most memory a single decode may claim, the largest frame a peer can send.
+/// Length prefixes come from untrusted bytes, so without a limit a forged one
+/// would make bincode try to allocate whatever it claims.
+const MAX_DECODE_SIZE: usize = 8 * 1024 * 1024;
+
+const DECODE_CONFIG: Configuration<BigEndian, Fixint, Limit<MAX_DECODE_SIZE>> =
+    CONFIG.with_limit::<MAX_DECODE_SIZE>();
+
+#[derive(Debug)]
+pub enum CodecError {
+    Empty,
+    UnsupportedVersion(u8),
+    Decode(String),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Empty => write!(f, "encoding is empty"),
+            CodecError::UnsupportedVersion(version) => {
+                write!(f, "unsupported encoding version {}", version)
+            }
+            CodecError::Decode(e) => write!(f, "failed to decode value: {}", e),
+            CodecError::TrailingBytes(count) => {
+                write!(f, "{} unexpected bytes after the encoded value", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![ENCODING_VERSION];
+    bincode::encode_into_std_write(value, &mut bytes, CONFIG)
+        .expect("writing to a Vec cannot fail");
+    bytes
+}
+
+pub fn decode<T: Decode<()>>(bytes: &[u8]) -> Result<T, CodecError> {
+    let (version, body) = bytes.split_first().ok_or(CodecError::Empty)?;
+    if *version != ENCODING_VERSION {
+        return Err(CodecError::UnsupportedVersion(*version));
+    }
+
+    let (value, read) = bincode::decode_from_slice(body, DECODE_CONFIG)
+        .map_err(|e| CodecError::Decode(e.to_string()))?;
+    if read != body.len() {
+        return Err(CodecError::TrailingBytes(body.len() - read));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::block::{Block, BlockHeader, BlockStatus};
+    use crate::chain::transaction::Transaction;
+    use crate::cryptography::hash::{Hasher, Keccak256};
+    use crate::utils::conversion::to_hex;
+
+    fn transaction() -> Transaction {
+        Transaction {
+            signer: vec![0x01; 32],
+            from: vec![0x01; 20],
+            to: vec![0x02; 20],
+            value: vec![5],
+            fee: 2,
+            timestamp: 1,
+            hash: [0x03; 32],
+            nonce: 7,
+            chain_id: 1,
+            signature: vec![0x04; 64],
+        }
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            previous_hash: [0; 32],
+            transactions_root: [0; 32],
+            state_root: [0; 32],
+            timestamp: 1,
+            bits: 0x1d00ffff,
+            nonce: 0,
+            height: 0,
+        }
+    }
+
+    #[test]
+    fn transaction_round_trips() {
+        let transaction = transaction();
+        let decoded: Transaction = decode(&encode(&transaction)).unwrap();
+        assert_eq!(encode(&decoded), encode(&transaction));
+        assert_eq!(decoded.hash, transaction.hash);
+        assert_eq!(decoded.signature, transaction.signature);
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = header();
+        let decoded: BlockHeader = decode(&encode(&header)).unwrap();
+        assert_eq!(encode(&decoded), encode(&header));
+        assert_eq!(decoded.hash(), header.hash());
+    }
+
+    #[test]
+    fn block_round_trips() {
+        let block = Block::new(
+            vec![Transaction::coinbase(&[0x05; 20], 50, 1, 1), transaction()],
+            [0x06; 32],
+            1,
+        );
+        let decoded: Block = decode(&encode(&block)).unwrap();
+        assert_eq!(encode(&decoded), encode(&block));
+        assert_eq!(decoded.hash, block.hash);
+        assert_eq!(decoded.body.transactions.len(), 2);
+    }
+
+    #[test]
+    fn block_encodes_header_and_body_only() {
+        let block = Block::new(vec![transaction()], [0x06; 32], 1);
+        let header = encode(&block.header);
+        let body = encode(&block.body);
+        assert_eq!(encode(&block), [header.as_slice(), &body[1..]].concat());
+
+        let decoded: Block = decode(&encode(&block)).unwrap();
+        assert_eq!(decoded.hash, block.header.hash());
+        assert!(matches!(decoded.status, BlockStatus::Finalized));
+    }
+
+    #[test]
+    fn transaction_signing_payload_matches_golden_vector() {
+        let expected = [
+            "05",
+            "0000000000000020",
+            &"01".repeat(32),
+            "0000000000000014",
+            &"01".repeat(20),
+            "0000000000000014",
+            &"02".repeat(20),
+            "0000000000000001",
+            "0000000000000005",
+            "0000000000000002",
+            "0000000000000001",
+            "0000000000000007",
+            "0000000000000001",
+        ]
+        .concat();
+
+        let payload = transaction().signing_payload();
+        assert_eq!(to_hex(&payload), expected);
+        assert_eq!(
+            to_hex(&Keccak256::hash(&payload)),
+            "f5828227f19c77a58a3c193c6a7f3ed1330aa0e41ad752a608be28dd5d3cc82e"
+        );
+    }
+
+    #[test]
+    fn header_matches_golden_vector() {
+        let expected = [
+            "05",
+            "00000001",
+            &"00".repeat(96),
+            "0000000000000001",
+            "1d00ffff",
+            "0000000000000000",
+            "0000000000000000",
+        ]
+        .concat();
+
+        let header = header();
+        assert_eq!(to_hex(&encode(&header)), expected);
+        assert_eq!(
+            to_hex(&header.hash()),
+            "9251bc1009fc9c239ff5368491f0bbbef204a08064a3b18b419c703a965afca2"
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut bytes = encode(&header());
+        bytes[0] = ENCODING_VERSION + 1;
+        assert!(matches!(
+            decode::<BlockHeader>(&bytes),
+            Err(CodecError::UnsupportedVersion(version)) if version == ENCODING_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = encode(&header());
+        bytes.extend_from_slice(&[0, 0, 0]);
+        assert!(matches!(
+            decode::<BlockHeader>(&bytes),
+            Err(CodecError::TrailingBytes(3))
+        ));
+    }
+
+    #[test]
+    fn rejects_forged_length_prefixes() {
+        let mut bytes = vec![ENCODING_VERSION];
+        bytes.extend_from_slice(&(1u64 << 40).to_be_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(matches!(
+            decode::<Vec<u8>>(&bytes),
+            Err(CodecError::Decode(_))
+        ));
+        assert!(matches!(
+            decode::<Vec<Transaction>>(&bytes),
+            Err(CodecError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_input() {
+        assert!(matches!(decode::<BlockHeader>(&[]), Err(CodecError::Empty)));
+    }
+}
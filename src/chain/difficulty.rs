@@ -0,0 +1,431 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::chain::block::BlockHeader;
+use crate::chain::target::U256;
+
+/// Read access to the headers of the branch a new block extends, by height.
+pub trait HeaderSource {
+    fn header_at(&self, height: u64) -> Option<&BlockHeader>;
+}
+
+/// Chooses the proof-of-work target of the next block from its ancestors.
+/// Every node must arrive at the same answer, so implementations only use
+/// integer arithmetic on header fields.
+pub trait DifficultyAlgorithm: Send + Sync {
+    /// Compact target required of the block extending `parent`. Targets are
+    /// kept between one and `pow_limit`.
+    fn next_bits(&self, parent: &BlockHeader, headers: &dyn HeaderSource, pow_limit: U256) -> u32;
+}
+
+/// A change of target between a block and its parent, recorded in the
+/// ledger's mining tree.
+#[derive(Debug, Clone, Deserialize, Serialize, Encode, Decode)]
+pub struct DifficultyUpdate {
+    /// Height of the first block mined at the new target.
+    pub height: u64,
+    /// Compact target of the block.
+    pub current: u32,
+    /// Compact target of its parent.
+    pub previous: u32,
+    /// Change in the expected number of hashes per block, positive when
+    /// mining got harder.
+    pub difference: i64,
+}
+
+impl DifficultyUpdate {
+    /// The update `block` makes over `parent`, or `None` when both carry the
+    /// same target.
+    pub fn between(parent: &BlockHeader, block: &BlockHeader) -> Option<Self> {
+        if parent.bits == block.bits {
+            return None;
+        }
+
+        let work = |bits| {
+            U256::from_compact(bits).map_or(0, |target| target.work().saturating_u64()) as i128
+        };
+        let difference =
+            (work(block.bits) - work(parent.bits)).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+
+        Some(Self {
+            height: block.height,
+            current: block.bits,
+            previous: parent.bits,
+            difference,
+        })
+    }
+}
+
+/// Difficulty algorithm as configured in the chain spec, e.g.
+/// `{"algorithm": "lwma", "window": 45}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum DifficultyConfig {
+    /// Retarget once every `interval` blocks from the time the interval took.
+    Epoch { interval: u64 },
+    /// Retarget every block from a linearly weighted moving average of the
+    /// last `window` solve times.
+    Lwma { window: u64 },
+    /// Retarget every block exponentially from how far the chain is ahead of
+    /// or behind schedule, halving or doubling the target per `half_life`
+    /// seconds of drift.
+    Asert { half_life: u64 },
+}
+
+impl Default for DifficultyConfig {
+    fn default() -> Self {
+        DifficultyConfig::Lwma { window: 45 }
+    }
+}
+
+impl DifficultyConfig {
+    pub fn build(&self, target_block_time: u64) -> Box<dyn DifficultyAlgorithm> {
+        let target_block_time = target_block_time.max(1);
+        match *self {
+            DifficultyConfig::Epoch { interval } => Box::new(Epoch {
+                interval: interval.max(1),
+                target_block_time,
+            }),
+            DifficultyConfig::Lwma { window } => Box::new(Lwma {
+                window: window.max(1),
+                target_block_time,
+            }),
+            DifficultyConfig::Asert { half_life } => Box::new(Asert {
+                half_life: half_life.max(1),
+                target_block_time,
+            }),
+        }
+    }
+}
+
+/// `target * numerator / denominator`, dividing first when the product
+/// would not fit.
+fn scale(target: U256, numerator: u64, denominator: u64) -> U256 {
+    let denominator = U256::from_u64(denominator);
+    match target.checked_mul_u64(numerator) {
+        Some(product) => product / denominator,
+        None => (target / denominator)
+            .checked_mul_u64(numerator)
+            .unwrap_or(U256::MAX),
+    }
+}
+
+fn clamp_to_compact(target: U256, pow_limit: U256) -> u32 {
+    target.max(U256::ONE).min(pow_limit).to_compact()
+}
+
+pub struct Epoch {
+    interval: u64,
+    target_block_time: u64,
+}
+
+impl DifficultyAlgorithm for Epoch {
+    fn next_bits(&self, parent: &BlockHeader, headers: &dyn HeaderSource, pow_limit: U256) -> u32 {
+        let height = parent.height + 1;
+        if !height.is_multiple_of(self.interval) {
+            return parent.bits;
+        }
+
+        // The interval's solve times run from the last block of the previous
+        // interval to the parent, so on-schedule blocks keep the target.
+        let first_height = parent.height.saturating_sub(self.interval);
+        let (Some(first), Some(target)) = (
+            headers.header_at(first_height),
+            U256::from_compact(parent.bits),
+        ) else {
+            return parent.bits;
+        };
+
+        let expected_time = self.target_block_time * (parent.height - first_height);
+        if expected_time == 0 {
+            return parent.bits;
+        }
+        let timespan = parent
+            .timestamp
+            .saturating_sub(first.timestamp)
+            .clamp(expected_time / 4, expected_time * 4);
+
+        clamp_to_compact(scale(target, timespan, expected_time), pow_limit)
+    }
+}
+
+/// Zawy's LWMA: recent solve times weigh more, so a hash rate change shows
+/// up within a few blocks.
+pub struct Lwma {
+    window: u64,
+    target_block_time: u64,
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn next_bits(&self, parent: &BlockHeader, headers: &dyn HeaderSource, pow_limit: U256) -> u32 {
+        let window = self.window.min(parent.height);
+        if window == 0 {
+            return parent.bits;
+        }
+
+        let first_height = parent.height - window;
+        let Some(mut previous) = headers.header_at(first_height) else {
+            return parent.bits;
+        };
+
+        let mut weighted_time = 0u64;
+        let mut total_target = Some(U256::ZERO);
+        let mut average_target = U256::ZERO;
+        for weight in 1..=window {
+            let Some(header) = headers.header_at(first_height + weight) else {
+                return parent.bits;
+            };
+            let Some(target) = U256::from_compact(header.bits) else {
+                return parent.bits;
+            };
+
+            // Bounded so out-of-order or stalled timestamps cannot swing the
+            // target too far in one block.
+            let solve_time = header
+                .timestamp
+                .saturating_sub(previous.timestamp)
+                .clamp(1, 6 * self.target_block_time);
+            weighted_time += solve_time * weight;
+            total_target = total_target.and_then(|total| total.checked_add(target));
+            average_target = average_target.saturating_add(target / U256::from_u64(window));
+            previous = header;
+        }
+        // Dividing each target on its own truncates, which would pull the
+        // target down a little every block, so that is only a fallback for
+        // sums that overflow.
+        if let Some(total) = total_target {
+            average_target = total / U256::from_u64(window);
+        }
+
+        let expected_time = window * (window + 1) / 2 * self.target_block_time;
+        clamp_to_compact(
+            scale(average_target, weighted_time, expected_time),
+            pow_limit,
+        )
+    }
+}
+
+/// The aserti3-2d rule from Bitcoin Cash, anchored at the first block after
+/// genesis so a chain that starts long after its genesis timestamp does not
+/// begin behind schedule.
+pub struct Asert {
+    half_life: u64,
+    target_block_time: u64,
+}
+
+const ASERT_ANCHOR_HEIGHT: u64 = 1;
+
+impl DifficultyAlgorithm for Asert {
+    fn next_bits(&self, parent: &BlockHeader, headers: &dyn HeaderSource, pow_limit: U256) -> u32 {
+        if parent.height < ASERT_ANCHOR_HEIGHT {
+            return parent.bits;
+        }
+
+        let Some(anchor) = headers.header_at(ASERT_ANCHOR_HEIGHT) else {
+            return parent.bits;
+        };
+        let Some(anchor_target) = U256::from_compact(anchor.bits) else {
+            return parent.bits;
+        };
+
+        let time_delta = parent.timestamp as i128 - anchor.timestamp as i128;
+        let height_delta = (parent.height - anchor.height) as i128;
+        let drift = time_delta - self.target_block_time as i128 * height_delta;
+
+        // 2^(drift / half_life) in 16.16 fixed point: the integer part is a
+        // shift and the fraction is approximated by a cubic polynomial.
+        let exponent = drift * 65536 / self.half_life as i128;
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xffff) as u128;
+        let factor = 65536
+            + ((195_766_423_245_049 * fraction
+                + 971_821_376 * fraction * fraction
+                + 5_127 * fraction * fraction * fraction
+                + (1 << 47))
+                >> 48) as u64;
+
+        let target = scale(anchor_target, factor, 65536);
+        let target = if shifts >= 0 {
+            if target.bits() as i128 + shifts > 256 {
+                pow_limit
+            } else {
+                target << shifts as u32
+            }
+        } else {
+            target >> shifts.unsigned_abs().min(256) as u32
+        };
+
+        clamp_to_compact(target, pow_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET_BLOCK_TIME: u64 = 600;
+    const BITS: u32 = 0x1d00ffff;
+    const POW_LIMIT_BITS: u32 = 0x1e00ffff;
+    const START: u64 = 1_700_000_000;
+
+    struct Headers(Vec<BlockHeader>);
+
+    impl HeaderSource for Headers {
+        fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+            self.0.get(height as usize)
+        }
+    }
+
+    fn header(height: u64, timestamp: u64, bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            previous_hash: [0; 32],
+            transactions_root: [0; 32],
+            state_root: [0; 32],
+            timestamp,
+            bits,
+            nonce: 0,
+            height,
+        }
+    }
+
+    /// Blocks `0..=tip` at `bits`, each `spacing` seconds after its parent.
+    fn chain(tip: u64, spacing: u64, bits: u32) -> Headers {
+        Headers(
+            (0..=tip)
+                .map(|height| header(height, START + height * spacing, bits))
+                .collect(),
+        )
+    }
+
+    fn next_bits(config: DifficultyConfig, headers: &Headers, pow_limit_bits: u32) -> u32 {
+        let parent = headers.0.last().unwrap();
+        config.build(TARGET_BLOCK_TIME).next_bits(
+            parent,
+            headers,
+            U256::from_compact(pow_limit_bits).unwrap(),
+        )
+    }
+
+    fn target(bits: u32) -> U256 {
+        U256::from_compact(bits).unwrap()
+    }
+
+    const EPOCH: DifficultyConfig = DifficultyConfig::Epoch { interval: 10 };
+    const LWMA: DifficultyConfig = DifficultyConfig::Lwma { window: 45 };
+    const ASERT: DifficultyConfig = DifficultyConfig::Asert { half_life: 3600 };
+
+    #[test]
+    fn on_schedule_blocks_keep_the_target() {
+        for config in [EPOCH, LWMA, ASERT] {
+            for tip in [9, 19, 59] {
+                let headers = chain(tip, TARGET_BLOCK_TIME, BITS);
+                assert_eq!(
+                    next_bits(config, &headers, POW_LIMIT_BITS),
+                    BITS,
+                    "{:?} at {}",
+                    config,
+                    tip
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn epoch_only_retargets_at_interval_boundaries() {
+        let headers = chain(14, TARGET_BLOCK_TIME / 2, BITS);
+        assert_eq!(next_bits(EPOCH, &headers, POW_LIMIT_BITS), BITS);
+
+        let headers = chain(19, TARGET_BLOCK_TIME / 2, BITS);
+        assert_eq!(
+            next_bits(EPOCH, &headers, POW_LIMIT_BITS),
+            (target(BITS) / U256::from_u64(2)).to_compact()
+        );
+
+        let headers = chain(19, TARGET_BLOCK_TIME * 2, BITS);
+        assert_eq!(
+            next_bits(EPOCH, &headers, POW_LIMIT_BITS),
+            (target(BITS) << 1).to_compact()
+        );
+    }
+
+    #[test]
+    fn epoch_bounds_the_timespan_to_a_factor_of_four() {
+        let headers = chain(19, TARGET_BLOCK_TIME * 100, BITS);
+        assert_eq!(
+            next_bits(EPOCH, &headers, POW_LIMIT_BITS),
+            (target(BITS) << 2).to_compact()
+        );
+    }
+
+    #[test]
+    fn fast_blocks_lower_and_slow_blocks_raise_the_target() {
+        for config in [EPOCH, LWMA, ASERT] {
+            let fast = chain(59, TARGET_BLOCK_TIME / 2, BITS);
+            let slow = chain(59, TARGET_BLOCK_TIME * 2, BITS);
+            assert!(
+                target(next_bits(config, &fast, POW_LIMIT_BITS)) < target(BITS),
+                "{:?}",
+                config
+            );
+            assert!(
+                target(next_bits(config, &slow, POW_LIMIT_BITS)) > target(BITS),
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn slow_blocks_never_exceed_the_pow_limit() {
+        for config in [EPOCH, LWMA, ASERT] {
+            let headers = chain(59, TARGET_BLOCK_TIME * 4, POW_LIMIT_BITS);
+            assert_eq!(
+                next_bits(config, &headers, POW_LIMIT_BITS),
+                POW_LIMIT_BITS,
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    /// Anchor at height 1 and parent `blocks` later, `drift` seconds off
+    /// schedule.
+    fn asert_chain(blocks: u64, drift: i64) -> Headers {
+        let mut headers = chain(1 + blocks, TARGET_BLOCK_TIME, BITS);
+        let parent = headers.0.last_mut().unwrap();
+        parent.timestamp = parent.timestamp.checked_add_signed(drift).unwrap();
+        headers
+    }
+
+    #[test]
+    fn asert_doubles_and_halves_per_half_life() {
+        assert_eq!(
+            next_bits(ASERT, &asert_chain(10, 3600), POW_LIMIT_BITS),
+            (target(BITS) << 1).to_compact()
+        );
+        assert_eq!(
+            next_bits(ASERT, &asert_chain(10, -3600), POW_LIMIT_BITS),
+            (target(BITS) >> 1).to_compact()
+        );
+        assert_eq!(
+            next_bits(ASERT, &asert_chain(10, 2 * 3600), POW_LIMIT_BITS),
+            (target(BITS) << 2).to_compact()
+        );
+    }
+
+    #[test]
+    fn asert_matches_the_reference_polynomial() {
+        // Half a half-life ahead: the aserti3-2d cubic gives a factor of
+        // 92674 / 65536 for 2^0.5, so the target is 0xffff << 208 scaled by
+        // it, 0x016a0095fe << 192.
+        assert_eq!(
+            next_bits(ASERT, &asert_chain(10, 1800), POW_LIMIT_BITS),
+            0x1d016a00
+        );
+        assert_eq!(
+            target(BITS).checked_mul_u64(92674).unwrap() / U256::from_u64(65536),
+            U256::from_u64(0x016a0095fe) << 192
+        );
+    }
+}
@@ -0,0 +1,518 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fmt;
+
+use crate::account::state::StateError;
+use crate::chain::transaction::{FeeRate, Transaction};
+use crate::utils::conversion::to_hex;
+
+/// How much a replacement must raise the fee of the transaction it replaces,
+/// in percent. It always has to pay at least one unit more.
+const REPLACEMENT_FEE_BUMP_PERCENT: u64 = 10;
+
+#[derive(Debug)]
+pub enum MempoolError {
+    Coinbase,
+    InvalidSignature,
+    WrongChain { expected: u64 },
+    AlreadyKnown,
+    TooLarge,
+    StaleNonce { expected: u64 },
+    NonceGap { expected: u64 },
+    InsufficientBalance,
+    Underpriced,
+    Full,
+    InvalidState(StateError),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Coinbase => write!(f, "coinbase transactions only come with blocks"),
+            MempoolError::InvalidSignature => write!(f, "invalid transaction signature"),
+            MempoolError::WrongChain { expected } => {
+                write!(
+                    f,
+                    "transaction is signed for another chain, expected {}",
+                    expected
+                )
+            }
+            MempoolError::AlreadyKnown => write!(f, "transaction is already pooled"),
+            MempoolError::TooLarge => write!(f, "transaction exceeds the mempool size"),
+            MempoolError::StaleNonce { expected } => {
+                write!(f, "nonce already used, account is at nonce {}", expected)
+            }
+            MempoolError::NonceGap { expected } => {
+                write!(f, "nonce leaves a gap, next nonce is {}", expected)
+            }
+            MempoolError::InsufficientBalance => {
+                write!(f, "balance does not cover the sender's pooled transactions")
+            }
+            MempoolError::Underpriced => write!(
+                f,
+                "replacement must raise the fee by {}% without lowering the fee rate",
+                REPLACEMENT_FEE_BUMP_PERCENT
+            ),
+            MempoolError::Full => write!(f, "mempool is full of transactions paying more"),
+            MempoolError::InvalidState(e) => write!(f, "failed to read sender account: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+#[derive(Debug)]
+struct PooledTransaction {
+    transaction: Transaction,
+    size: usize,
+}
+
+impl PooledTransaction {
+    fn fee_rate(&self) -> FeeRate {
+        FeeRate {
+            fee: self.transaction.fee,
+            size: self.size as u64,
+        }
+    }
+}
+
+/// Transactions waiting to be mined, indexed by hash and by sender. Each
+/// sender's transactions run without gaps from the sender's account nonce and
+/// together fit the sender's spendable balance, so any nonce-ordered prefix
+/// of them applies cleanly on top of the tip.
+#[derive(Debug)]
+pub struct Mempool {
+    transactions: HashMap<[u8; 32], PooledTransaction>,
+    senders: HashMap<Vec<u8>, BTreeMap<u64, [u8; 32]>>,
+    size: usize,
+    max_transactions: usize,
+    max_size: usize,
+}
+
+impl Mempool {
+    pub fn new(max_transactions: usize, max_size: usize) -> Self {
+        Self {
+            transactions: HashMap::new(),
+            senders: HashMap::new(),
+            size: 0,
+            max_transactions,
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Encoded size of every pooled transaction, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&Transaction> {
+        self.transactions
+            .get(hash)
+            .map(|pooled| &pooled.transaction)
+    }
+
+    /// Pooled transactions, each sender's in nonce order.
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.senders
+            .values()
+            .flat_map(|queue| queue.values())
+            .map(|hash| &self.transactions[hash].transaction)
+    }
+
+    /// Admits a transaction from a sender whose account is at `account_nonce`
+    /// and who can spend `spendable`.
+    pub fn add(
+        &mut self,
+        transaction: Transaction,
+        account_nonce: u64,
+        spendable: u64,
+    ) -> Result<(), MempoolError> {
+        if transaction.is_coinbase() {
+            return Err(MempoolError::Coinbase);
+        }
+
+        if !transaction.verify_signature() {
+            return Err(MempoolError::InvalidSignature);
+        }
+
+        self.insert(transaction, account_nonce, spendable)
+    }
+
+    /// Like `add`, for transactions whose signature was already verified,
+    /// such as those of disconnected blocks.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        account_nonce: u64,
+        spendable: u64,
+    ) -> Result<(), MempoolError> {
+        if self.transactions.contains_key(&transaction.hash) {
+            return Err(MempoolError::AlreadyKnown);
+        }
+
+        let size = transaction.size();
+        if size > self.max_size {
+            return Err(MempoolError::TooLarge);
+        }
+
+        if transaction.nonce < account_nonce {
+            return Err(MempoolError::StaleNonce {
+                expected: account_nonce,
+            });
+        }
+
+        let queue = self.senders.get(&transaction.from);
+        let next_nonce = account_nonce + queue.map_or(0, |queue| queue.len() as u64);
+        if transaction.nonce > next_nonce {
+            return Err(MempoolError::NonceGap {
+                expected: next_nonce,
+            });
+        }
+
+        let replaced = queue
+            .and_then(|queue| queue.get(&transaction.nonce))
+            .map(|hash| &self.transactions[hash]);
+        if let Some(replaced) = replaced {
+            if !Self::is_replacement(replaced, &transaction) {
+                return Err(MempoolError::Underpriced);
+            }
+        }
+
+        let cost = queue
+            .into_iter()
+            .flat_map(|queue| queue.iter())
+            .filter(|(nonce, _)| **nonce != transaction.nonce)
+            .map(|(_, hash)| self.transactions[hash].transaction.total_cost())
+            .chain(std::iter::once(transaction.total_cost()))
+            .try_fold(0u64, |total, cost| total.checked_add(cost?));
+        if cost.is_none_or(|cost| cost > spendable) {
+            return Err(MempoolError::InsufficientBalance);
+        }
+
+        let replaced = replaced.map(|replaced| (replaced.transaction.hash, replaced.size));
+        let freed = replaced.map_or(0, |(_, size)| size);
+        let evicted = self.eviction_plan(
+            &transaction,
+            self.transactions.len() + usize::from(replaced.is_none()),
+            self.size - freed + size,
+        )?;
+
+        if let Some((hash, _)) = replaced {
+            println!(
+                "Replacing pooled transaction {} with {}",
+                to_hex(&hash),
+                transaction
+            );
+            self.remove(&hash);
+        }
+        for hash in evicted {
+            println!("Evicting transaction {} from full mempool", to_hex(&hash));
+            self.remove(&hash);
+        }
+
+        self.senders
+            .entry(transaction.from.clone())
+            .or_default()
+            .insert(transaction.nonce, transaction.hash);
+        self.size += size;
+        self.transactions
+            .insert(transaction.hash, PooledTransaction { transaction, size });
+        Ok(())
+    }
+
+    /// A replacement has to pay the bumped fee and may not lower the fee
+    /// rate, so miners always prefer it.
+    fn is_replacement(replaced: &PooledTransaction, transaction: &Transaction) -> bool {
+        let bump = (replaced
+            .transaction
+            .fee
+            .saturating_mul(REPLACEMENT_FEE_BUMP_PERCENT)
+            / 100)
+            .max(1);
+        transaction.fee >= replaced.transaction.fee.saturating_add(bump)
+            && transaction.fee_rate() >= replaced.fee_rate()
+    }
+
+    /// Transactions to evict so a pool of `count` transactions and `size`
+    /// bytes fits its limits. Only the last transaction of a sender can go,
+    /// so no sender is left with a gap, and only for a lower fee rate than
+    /// the incoming transaction. Nothing is evicted to make room for a
+    /// sender's own transaction.
+    fn eviction_plan(
+        &self,
+        incoming: &Transaction,
+        mut count: usize,
+        mut size: usize,
+    ) -> Result<Vec<[u8; 32]>, MempoolError> {
+        if count <= self.max_transactions && size <= self.max_size {
+            return Ok(Vec::new());
+        }
+
+        let incoming_rate = incoming.fee_rate();
+
+        let mut candidates: BinaryHeap<_> = self
+            .senders
+            .iter()
+            .filter(|(sender, _)| **sender != incoming.from)
+            .filter_map(|(sender, queue)| {
+                let (nonce, hash) = queue.last_key_value()?;
+                Some((Reverse(self.transactions[hash].fee_rate()), *nonce, sender))
+            })
+            .collect();
+
+        let mut evicted = Vec::new();
+        while count > self.max_transactions || size > self.max_size {
+            let Some((Reverse(fee_rate), nonce, sender)) = candidates.pop() else {
+                return Err(MempoolError::Full);
+            };
+            if fee_rate >= incoming_rate {
+                return Err(MempoolError::Full);
+            }
+
+            let queue = &self.senders[sender];
+            let hash = queue[&nonce];
+            count -= 1;
+            size -= self.transactions[&hash].size;
+            evicted.push(hash);
+
+            if let Some((nonce, hash)) = queue.range(..nonce).next_back() {
+                candidates.push((Reverse(self.transactions[hash].fee_rate()), *nonce, sender));
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    pub fn remove(&mut self, hash: &[u8; 32]) -> Option<Transaction> {
+        let pooled = self.transactions.remove(hash)?;
+        let transaction = pooled.transaction;
+
+        if let Some(queue) = self.senders.get_mut(&transaction.from) {
+            queue.remove(&transaction.nonce);
+            if queue.is_empty() {
+                self.senders.remove(&transaction.from);
+            }
+        }
+        self.size -= pooled.size;
+        Some(transaction)
+    }
+
+    /// Removes every transaction of `sender`, returning them in nonce order.
+    pub fn remove_sender(&mut self, sender: &[u8]) -> Vec<Transaction> {
+        let Some(queue) = self.senders.remove(sender) else {
+            return Vec::new();
+        };
+
+        queue
+            .into_values()
+            .filter_map(|hash| self.transactions.remove(&hash))
+            .map(|pooled| {
+                self.size -= pooled.size;
+                pooled.transaction
+            })
+            .collect()
+    }
+
+    /// Picks transactions in order of fee rate until `budget` bytes are used,
+    /// leaving the pool untouched. Each sender's transactions stay in nonce
+    /// order, so only a sender's lowest remaining nonce competes at any time,
+    /// and a sender whose next transaction does not fit is skipped.
+    pub fn select(&self, mut budget: usize) -> Vec<Transaction> {
+        let mut queues: HashMap<&[u8], _> = self
+            .senders
+            .iter()
+            .map(|(sender, queue)| (sender.as_slice(), queue.values().peekable()))
+            .collect();
+
+        let mut candidates = BinaryHeap::new();
+        for (sender, queue) in queues.iter_mut() {
+            if let Some(hash) = queue.peek() {
+                candidates.push((self.transactions[*hash].fee_rate(), *sender));
+            }
+        }
+
+        let mut selected = Vec::new();
+        while let Some((_, sender)) = candidates.pop() {
+            let Some(queue) = queues.get_mut(sender) else {
+                continue;
+            };
+            let Some(pooled) = queue.next().map(|hash| &self.transactions[hash]) else {
+                continue;
+            };
+            if pooled.size > budget {
+                continue;
+            }
+
+            budget -= pooled.size;
+            selected.push(pooled.transaction.clone());
+            if let Some(hash) = queue.peek() {
+                candidates.push((self.transactions[*hash].fee_rate(), sender));
+            }
+        }
+
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPENDABLE: u64 = 1_000_000;
+
+    fn transaction(sender: u8, nonce: u64, value: u64, fee: u64) -> Transaction {
+        let mut hash = [sender; 32];
+        hash[..8].copy_from_slice(&nonce.to_be_bytes());
+        hash[8..16].copy_from_slice(&fee.to_be_bytes());
+        hash[16..24].copy_from_slice(&value.to_be_bytes());
+        Transaction {
+            signer: vec![sender; 32],
+            from: vec![sender; 20],
+            to: vec![0xee; 20],
+            value: vec![value],
+            fee,
+            timestamp: 1,
+            hash,
+            nonce,
+            chain_id: 1,
+            signature: vec![0; 64],
+        }
+    }
+
+    fn hashes(transactions: &[Transaction]) -> Vec<[u8; 32]> {
+        transactions.iter().map(|tx| tx.hash).collect()
+    }
+
+    #[test]
+    fn rejects_stale_nonces_and_gaps() {
+        let mut mempool = Mempool::new(10, 10_000);
+        assert!(matches!(
+            mempool.insert(transaction(1, 4, 1, 1), 5, SPENDABLE),
+            Err(MempoolError::StaleNonce { expected: 5 })
+        ));
+        assert!(matches!(
+            mempool.insert(transaction(1, 6, 1, 1), 5, SPENDABLE),
+            Err(MempoolError::NonceGap { expected: 5 })
+        ));
+
+        mempool
+            .insert(transaction(1, 5, 1, 1), 5, SPENDABLE)
+            .unwrap();
+        mempool
+            .insert(transaction(1, 6, 1, 1), 5, SPENDABLE)
+            .unwrap();
+        assert!(matches!(
+            mempool.insert(transaction(1, 8, 1, 1), 5, SPENDABLE),
+            Err(MempoolError::NonceGap { expected: 7 })
+        ));
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn counts_queued_transactions_against_the_balance() {
+        let mut mempool = Mempool::new(10, 10_000);
+        mempool.insert(transaction(1, 0, 60, 1), 0, 100).unwrap();
+        assert!(matches!(
+            mempool.insert(transaction(1, 1, 39, 1), 0, 100),
+            Err(MempoolError::InsufficientBalance)
+        ));
+        mempool.insert(transaction(1, 1, 38, 1), 0, 100).unwrap();
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn replacements_must_pay_the_fee_bump() {
+        let mut mempool = Mempool::new(10, 10_000);
+        let original = transaction(1, 0, 1, 100);
+        mempool.insert(original.clone(), 0, SPENDABLE).unwrap();
+
+        assert!(matches!(
+            mempool.insert(transaction(1, 0, 1, 109), 0, SPENDABLE),
+            Err(MempoolError::Underpriced)
+        ));
+
+        let mut larger = transaction(1, 0, 1, 110);
+        larger.value = vec![1; 100];
+        assert!(matches!(
+            mempool.insert(larger, 0, SPENDABLE),
+            Err(MempoolError::Underpriced)
+        ));
+
+        let replacement = transaction(1, 0, 1, 110);
+        mempool.insert(replacement.clone(), 0, SPENDABLE).unwrap();
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.get(&original.hash).is_none());
+        assert!(mempool.get(&replacement.hash).is_some());
+        assert_eq!(mempool.size(), replacement.size());
+    }
+
+    #[test]
+    fn evicts_the_cheapest_last_transaction_of_other_senders() {
+        let mut mempool = Mempool::new(3, 10_000);
+        let cheap_first = transaction(1, 0, 1, 5);
+        let dear_second = transaction(1, 1, 1, 50);
+        let other = transaction(2, 0, 1, 20);
+        for tx in [&cheap_first, &dear_second, &other] {
+            mempool.insert(tx.clone(), 0, SPENDABLE).unwrap();
+        }
+
+        // The cheapest transaction is not the last of its sender, so taking
+        // it would leave a gap.
+        let incoming = transaction(3, 0, 1, 30);
+        mempool.insert(incoming.clone(), 0, SPENDABLE).unwrap();
+        assert!(mempool.get(&other.hash).is_none());
+        assert!(mempool.get(&cheap_first.hash).is_some());
+        assert!(mempool.get(&dear_second.hash).is_some());
+
+        // The incoming sender's own transactions are spared.
+        let own = transaction(1, 2, 1, 100);
+        mempool.insert(own.clone(), 0, SPENDABLE).unwrap();
+        assert!(mempool.get(&incoming.hash).is_none());
+        assert_eq!(
+            mempool.transactions().map(|tx| tx.hash).collect::<Vec<_>>(),
+            hashes(&[cheap_first, dear_second, own])
+        );
+
+        assert!(matches!(
+            mempool.insert(transaction(4, 0, 1, 1), 0, SPENDABLE),
+            Err(MempoolError::Full)
+        ));
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn selects_by_fee_rate_within_the_budget() {
+        let mut mempool = Mempool::new(10, 10_000);
+        let low_first = transaction(1, 0, 1, 10);
+        let high_second = transaction(1, 1, 1, 100);
+        let high = transaction(2, 0, 1, 50);
+        let middle = transaction(3, 0, 1, 30);
+        for tx in [&low_first, &high_second, &high, &middle] {
+            mempool.insert(tx.clone(), 0, SPENDABLE).unwrap();
+        }
+        let size = high.size();
+
+        assert!(mempool.select(size - 1).is_empty());
+        assert_eq!(
+            hashes(&mempool.select(2 * size)),
+            hashes(&[high.clone(), middle.clone()])
+        );
+        assert_eq!(
+            hashes(&mempool.select(3 * size)),
+            hashes(&[high.clone(), middle.clone(), low_first.clone()])
+        );
+        assert_eq!(
+            hashes(&mempool.select(4 * size)),
+            hashes(&[high, middle, low_first, high_second])
+        );
+        assert_eq!(mempool.len(), 4);
+    }
+}
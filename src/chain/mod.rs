@@ -1,4 +1,11 @@
 pub mod block;
 pub mod block_manager;
 pub mod blockchain;
+pub mod codec;
+pub mod difficulty;
+pub mod mempool;
+pub mod orphan_pool;
+pub mod reward;
+pub mod spec;
+pub mod target;
 pub mod transaction;
@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+use hashlink::LinkedHashMap;
+
+use crate::chain::block::Block;
+use crate::utils::conversion::to_hex;
+
+#[derive(Debug)]
+struct OrphanBlock {
+    block: Block,
+    received_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct OrphanPool {
+    orphans: LinkedHashMap<[u8; 32], OrphanBlock>,
+    max_orphans: usize,
+    max_age: Duration,
+}
+
+impl OrphanPool {
+    pub fn new(max_orphans: usize, max_age_secs: u64) -> Self {
+        Self {
+            orphans: LinkedHashMap::new(),
+            max_orphans,
+            max_age: Duration::from_secs(max_age_secs),
+        }
+    }
+
+    /// Stores a block whose parent is unknown and returns the hash of the
+    /// ancestor that is actually missing, walking back through any orphans
+    /// already waiting in the pool.
+    pub fn insert(&mut self, block: Block) -> [u8; 32] {
+        self.prune_expired();
+
+        if !self.orphans.contains_key(&block.hash) {
+            while self.orphans.len() >= self.max_orphans {
+                match self.orphans.pop_front() {
+                    Some((hash, _)) => {
+                        println!("Evicting orphan block {} from full pool", to_hex(&hash))
+                    }
+                    None => break,
+                }
+            }
+
+            self.orphans.insert(
+                block.hash,
+                OrphanBlock {
+                    block: block.clone(),
+                    received_at: Instant::now(),
+                },
+            );
+        }
+
+        let mut missing = block.header.previous_hash;
+        while let Some(orphan) = self.orphans.get(&missing) {
+            missing = orphan.block.header.previous_hash;
+        }
+        missing
+    }
+
+    pub fn take_children(&mut self, parent_hash: &[u8; 32]) -> Vec<Block> {
+        self.prune_expired();
+
+        let child_hashes: Vec<[u8; 32]> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.block.header.previous_hash == *parent_hash)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        child_hashes
+            .iter()
+            .filter_map(|hash| self.orphans.remove(hash))
+            .map(|orphan| orphan.block)
+            .collect()
+    }
+
+    fn prune_expired(&mut self) {
+        while let Some((_, orphan)) = self.orphans.front() {
+            if orphan.received_at.elapsed() < self.max_age {
+                break;
+            }
+            self.orphans.pop_front();
+        }
+    }
+}
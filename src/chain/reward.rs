@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Block reward parameters from the chain spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RewardSchedule {
+    /// Subsidy paid by the coinbase of the first blocks, before any halving.
+    pub initial_subsidy: u64,
+    /// Number of blocks after which the subsidy halves.
+    pub halving_interval: u64,
+    /// Number of blocks a coinbase reward waits before it can be spent.
+    pub maturity: u64,
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        Self {
+            initial_subsidy: 50,
+            halving_interval: 210_000,
+            maturity: 100,
+        }
+    }
+}
+
+impl RewardSchedule {
+    /// Subsidy of the block at `height`. Genesis pays none.
+    pub fn subsidy(&self, height: u64) -> u64 {
+        if height == 0 {
+            return 0;
+        }
+
+        let halvings = height / self.halving_interval.max(1);
+        if halvings >= u64::BITS as u64 {
+            return 0;
+        }
+        self.initial_subsidy >> halvings
+    }
+}
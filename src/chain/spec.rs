@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use crate::chain::block::Block;
+use crate::chain::difficulty::DifficultyConfig;
+use crate::chain::reward::RewardSchedule;
+use crate::chain::target::U256;
+use crate::utils::conversion::from_hex;
+use crate::utils::env::get_chain_spec_path;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GenesisAllocation {
+    pub address: String,
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChainSpec {
+    pub chain_id: u64,
+    pub genesis_timestamp: u64,
+    /// Compact proof-of-work target of the genesis block, also the easiest
+    /// target any block may claim.
+    pub initial_bits: u32,
+    /// Seconds between blocks the difficulty algorithm aims for.
+    #[serde(default = "default_target_block_time")]
+    pub target_block_time: u64,
+    #[serde(default)]
+    pub difficulty: DifficultyConfig,
+    #[serde(default)]
+    pub rewards: RewardSchedule,
+    /// Largest block, in bytes of its canonical encoding.
+    #[serde(default = "default_max_block_size")]
+    pub max_block_size: u64,
+    pub allocations: Vec<GenesisAllocation>,
+}
+
+fn default_target_block_time() -> u64 {
+    600
+}
+
+fn default_max_block_size() -> u64 {
+    1_000_000
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        Self {
+            chain_id: 1,
+            genesis_timestamp: 1_745_020_800,
+            initial_bits: 0x207f_ffff,
+            target_block_time: default_target_block_time(),
+            difficulty: DifficultyConfig::default(),
+            rewards: RewardSchedule::default(),
+            max_block_size: default_max_block_size(),
+            allocations: vec![],
+        }
+    }
+}
+
+impl ChainSpec {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let contents = std::fs::read_to_string(path)?;
+        let spec: ChainSpec = serde_json::from_str(&contents)?;
+        spec.allocation_balances()?;
+        if U256::from_compact(spec.initial_bits).is_none() {
+            return Err(format!("Invalid initial bits: {:#010x}", spec.initial_bits).into());
+        }
+        Ok(spec)
+    }
+
+    /// Loads the spec pointed to by `CHAIN_SPEC_PATH`, falling back to the
+    /// built-in default network when the variable is unset.
+    pub fn from_env() -> Self {
+        match get_chain_spec_path() {
+            Some(path) => match Self::load(&path) {
+                Ok(spec) => {
+                    println!(
+                        "Loaded chain spec from {} (chain id {})",
+                        path, spec.chain_id
+                    );
+                    spec
+                }
+                Err(e) => panic!("Failed to load chain spec from {}: {}", path, e),
+            },
+            None => {
+                println!("CHAIN_SPEC_PATH not set, using the default chain spec");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn genesis_block(&self, state_root: [u8; 32]) -> Block {
+        Block::genesis(self.genesis_timestamp, self.initial_bits, state_root)
+    }
+
+    pub fn allocation_balances(&self) -> Result<Vec<(Vec<u8>, u64)>, String> {
+        self.allocations
+            .iter()
+            .map(|allocation| match from_hex(&allocation.address) {
+                Ok(address) if !address.is_empty() => Ok((address, allocation.balance)),
+                _ => Err(format!(
+                    "Invalid genesis allocation address: {}",
+                    allocation.address
+                )),
+            })
+            .collect()
+    }
+}
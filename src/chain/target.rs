@@ -0,0 +1,328 @@
+use std::fmt;
+use std::ops::{Div, Not, Shl, Shr};
+
+use crate::utils::conversion::to_hex;
+
+/// Unsigned 256-bit integer for proof-of-work targets and chain work, stored
+/// as four limbs with the most significant first so the derived ordering is
+/// numeric.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([0, 0, 0, 1]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(value: u64) -> Self {
+        U256([0, 0, 0, value])
+    }
+
+    /// Reads a digest as a big-endian integer.
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_be_bytes(chunk.try_into().expect("chunks are 8 bytes"));
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    fn low_u64(&self) -> u64 {
+        self.0[3]
+    }
+
+    /// The value as a `u64`, or `u64::MAX` when it does not fit.
+    pub fn saturating_u64(self) -> u64 {
+        if self.bits() > 64 {
+            u64::MAX
+        } else {
+            self.low_u64()
+        }
+    }
+
+    /// Number of significant bits.
+    pub fn bits(&self) -> u32 {
+        for (index, limb) in self.0.iter().enumerate() {
+            if *limb != 0 {
+                return 64 * (4 - index as u32) - limb.leading_zeros();
+            }
+        }
+        0
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        self.0[3 - (index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, index: u32) {
+        self.0[3 - (index / 64) as usize] |= 1 << (index % 64);
+    }
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for index in (0..4).rev() {
+            let (sum, overflow) = self.0[index].overflowing_add(other.0[index]);
+            let (sum, carried) = sum.overflowing_add(carry as u64);
+            limbs[index] = sum;
+            carry = overflow || carried;
+        }
+        (!carry).then_some(U256(limbs))
+    }
+
+    pub fn saturating_add(self, other: U256) -> U256 {
+        self.checked_add(other).unwrap_or(U256::MAX)
+    }
+
+    fn wrapping_sub(self, other: U256) -> U256 {
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for index in (0..4).rev() {
+            let (difference, underflow) = self.0[index].overflowing_sub(other.0[index]);
+            let (difference, borrowed) = difference.overflowing_sub(borrow as u64);
+            limbs[index] = difference;
+            borrow = underflow || borrowed;
+        }
+        U256(limbs)
+    }
+
+    pub fn checked_mul_u64(self, factor: u64) -> Option<U256> {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for index in (0..4).rev() {
+            let product = self.0[index] as u128 * factor as u128 + carry;
+            limbs[index] = product as u64;
+            carry = product >> 64;
+        }
+        (carry == 0).then_some(U256(limbs))
+    }
+
+    /// Decodes a compact target as used in Bitcoin's `nBits`: the top byte is
+    /// the length of the target in bytes and the low 23 bits are its most
+    /// significant digits. Negative, overflowing and zero targets are
+    /// rejected.
+    pub fn from_compact(bits: u32) -> Option<U256> {
+        let size = bits >> 24;
+        let word = bits & 0x007f_ffff;
+        if word != 0 && bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        if word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32)) {
+            return None;
+        }
+
+        let target = if size <= 3 {
+            U256::from_u64((word >> (8 * (3 - size))) as u64)
+        } else {
+            U256::from_u64(word as u64) << (8 * (size - 3))
+        };
+        (!target.is_zero()).then_some(target)
+    }
+
+    /// Compact form of the target, dropping every digit past the first 23
+    /// significant bits.
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (self >> (8 * (size - 3))).low_u64() as u32
+        };
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size << 24)
+    }
+
+    /// Expected number of hashes needed to find a digest at or below this
+    /// target, `2^256 / (target + 1)`.
+    pub fn work(&self) -> U256 {
+        match self.checked_add(U256::ONE) {
+            // 2^256 / (t + 1) == (2^256 - 1 - t) / (t + 1) + 1, which fits.
+            Some(divisor) => (!*self / divisor).saturating_add(U256::ONE),
+            None => U256::ONE,
+        }
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        if shift >= 256 {
+            return U256::ZERO;
+        }
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        let mut result = [0u64; 4];
+        for (index, limb) in result.iter_mut().enumerate().take(4 - limbs) {
+            *limb = self.0[index + limbs] << bits;
+            if bits > 0 && index + limbs + 1 < 4 {
+                *limb |= self.0[index + limbs + 1] >> (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        if shift >= 256 {
+            return U256::ZERO;
+        }
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        let mut result = [0u64; 4];
+        for (index, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[index - limbs] >> bits;
+            if bits > 0 && index > limbs {
+                *limb |= self.0[index - limbs - 1] << (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    /// Long division, one bit at a time. Panics on a zero divisor.
+    fn div(self, divisor: U256) -> U256 {
+        assert!(!divisor.is_zero(), "division by zero");
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for index in (0..self.bits()).rev() {
+            let carry = remainder.bit(255);
+            remainder = remainder << 1;
+            if self.bit(index) {
+                remainder.set_bit(0);
+            }
+            if carry || remainder >= divisor {
+                remainder = remainder.wrapping_sub(divisor);
+                quotient.set_bit(index);
+            }
+        }
+        quotient
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.to_be_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_BITS: u32 = 0x1d00ffff;
+
+    #[test]
+    fn compact_round_trips() {
+        for bits in [GENESIS_BITS, 0x1b0404cb, 0x207fffff, 0x03123456, 0x01120000] {
+            let target = U256::from_compact(bits).unwrap();
+            assert_eq!(target.to_compact(), bits, "{:#010x}", bits);
+        }
+    }
+
+    #[test]
+    fn decodes_bitcoin_genesis_target_and_work() {
+        let target = U256::from_compact(GENESIS_BITS).unwrap();
+        let expected = [[0x00, 0x00, 0x00, 0x00, 0xff, 0xff].as_slice(), &[0x00; 26]].concat();
+        assert_eq!(target.to_be_bytes().as_slice(), expected.as_slice());
+        assert_eq!(target.work(), U256::from_u64(0x1_0001_0001));
+    }
+
+    #[test]
+    fn moves_digits_clear_of_the_sign_bit() {
+        assert_eq!(U256::from_u64(0x80).to_compact(), 0x02008000);
+        assert_eq!(U256::from_u64(0x80_0000).to_compact(), 0x04008000);
+        assert_eq!(U256::from_compact(0x02008000), Some(U256::from_u64(0x80)));
+    }
+
+    #[test]
+    fn rejects_negative_zero_and_overflowing_targets() {
+        assert_eq!(U256::from_compact(0x04923456), None);
+        assert_eq!(U256::from_compact(0x01800000), None);
+        assert_eq!(U256::from_compact(0x00800000), None);
+        assert_eq!(U256::from_compact(0x1d000000), None);
+        assert_eq!(U256::from_compact(0x23000001), None);
+        assert_eq!(U256::from_compact(0x22000100), None);
+        assert_eq!(U256::from_compact(0x21010000), None);
+        assert_eq!(U256::from_compact(0xff123456), None);
+        assert_eq!(U256::from_compact(0x22000001), Some(U256::ONE << 248));
+    }
+
+    #[test]
+    fn work_is_two_to_the_256_over_target_plus_one() {
+        assert_eq!(U256::MAX.work(), U256::ONE);
+        assert_eq!((U256::MAX >> 128).work(), U256::ONE << 128);
+        assert_eq!((U256::MAX >> 1).work(), U256::from_u64(2));
+        assert_eq!(U256::ZERO.work(), U256::MAX);
+    }
+
+    #[test]
+    fn shifts_at_the_edges() {
+        let top = U256::ONE << 255;
+        assert_eq!(top.bits(), 256);
+        assert_eq!(top >> 255, U256::ONE);
+        assert_eq!(U256::MAX >> 255, U256::ONE);
+        assert_eq!(U256::MAX << 255, top);
+        assert_eq!(U256::ONE << 256, U256::ZERO);
+        assert_eq!(U256::MAX >> 256, U256::ZERO);
+        assert_eq!(U256::MAX << 0, U256::MAX);
+        assert_eq!(U256::MAX >> 0, U256::MAX);
+        assert_eq!(
+            U256::from_u64(u64::MAX) << 68,
+            U256([0, 0xf, u64::MAX << 4, 0])
+        );
+        assert_eq!(
+            U256([0, 0xf, u64::MAX << 4, 0]) >> 68,
+            U256::from_u64(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn divides_at_the_edges() {
+        let top = U256::ONE << 255;
+        assert_eq!(U256::ZERO / U256::MAX, U256::ZERO);
+        assert_eq!(U256::MAX / U256::ONE, U256::MAX);
+        assert_eq!(U256::MAX / U256::MAX, U256::ONE);
+        assert_eq!(U256::MAX / top, U256::ONE);
+        assert_eq!(top / U256::from_u64(2), U256::ONE << 254);
+        assert_eq!(top / (top >> 1), U256::from_u64(2));
+        assert_eq!(
+            U256::from_u64(1_000_000) / U256::from_u64(7),
+            U256::from_u64(142_857)
+        );
+        assert_eq!(U256::from_u64(6) / U256::from_u64(7), U256::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "division by zero")]
+    fn division_by_zero_panics() {
+        let _ = U256::ONE / U256::ZERO;
+    }
+}
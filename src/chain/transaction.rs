@@ -1,63 +1,211 @@
+use bincode::{Decode, Encode};
+use ed25519_dalek::{Signature, PUBLIC_KEY_LENGTH};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
 
-use crate::chain::{block_manager::BlockManager, blockchain::Blockchain};
-use crate::cryptography::hash::transform;
-use crate::utils::conversion::to_hex;
+use crate::account::wallet::Wallet;
+use crate::chain::{blockchain::Blockchain, codec};
+use crate::cryptography::hash::{Hasher, Keccak256};
+use crate::cryptography::signature::verify;
+use crate::utils::conversion::{public_key_to_address, to_hex};
 use crate::utils::time::get_timestamp;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
 pub struct Transaction {
     pub signer: Vec<u8>,
     pub from: Vec<u8>,
     pub to: Vec<u8>,
     pub value: Vec<u64>,
+    /// Paid by the sender on top of `value` to the miner of the block.
+    pub fee: u64,
     pub timestamp: u64,
-    pub hash: Vec<u8>,
+    pub hash: [u8; 32],
     pub nonce: u64,
+    /// Chain the transaction was signed for, so it cannot be replayed on
+    /// another chain.
+    pub chain_id: u64,
+    pub signature: Vec<u8>,
 }
 
 impl Transaction {
+    #[allow(dead_code)]
     pub fn new(
-        from: &Vec<u8>,
-        to: &Vec<u8>,
+        wallet: &Wallet,
+        to: &[u8],
         value: Vec<u64>,
+        fee: u64,
         nonce: Option<u64>,
-        signer: &Vec<u8>,
         blockchain: &mut Blockchain,
-        block_manager: &mut BlockManager,
     ) -> Self {
-        let nonce = match nonce {
-            Some(nonce) => nonce,
-            None => 0,
-        };
+        let tx = Self::signed(
+            wallet,
+            to,
+            value,
+            fee,
+            nonce.unwrap_or_default(),
+            blockchain.chain_id,
+        );
+
+        if let Err(e) = blockchain.submit_transaction(tx.clone()) {
+            eprintln!("Transaction {} rejected by mempool: {}", tx, e);
+        }
+        tx
+    }
 
-        let mut s = String::new();
-        s.push_str(&to_hex(&from));
-        s.push_str(&to_hex(&to));
-        s.push_str(&to_hex(
-            &value
-                .iter()
-                .map(|value| value.to_be_bytes())
-                .flatten()
-                .collect::<Vec<u8>>(),
-        ));
-        s.push_str(&nonce.to_string());
-
-        let tx = Self {
-            signer: signer.clone(),
-            hash: transform(&s).into_bytes(),
-            from: from.clone(),
-            to: to.clone(),
+    pub fn signed(
+        wallet: &Wallet,
+        to: &[u8],
+        value: Vec<u64>,
+        fee: u64,
+        nonce: u64,
+        chain_id: u64,
+    ) -> Self {
+        let mut tx = Self {
+            signer: wallet.public_key.clone(),
+            hash: [0u8; 32],
+            from: wallet.address.clone(),
+            to: to.to_vec(),
             value,
+            fee,
             nonce,
+            chain_id,
+            timestamp: get_timestamp(),
+            signature: vec![],
+        };
+
+        tx.hash = tx.compute_hash();
+        tx.signature = wallet.sign(&tx.signing_payload());
+        tx
+    }
+
+    /// Block reward paying `value` to `to`. It has no sender or signature
+    /// and takes the block height as its nonce, so the coinbase of every
+    /// block hashes differently.
+    pub fn coinbase(to: &[u8], value: u64, height: u64, chain_id: u64) -> Self {
+        let mut tx = Self {
+            signer: vec![],
+            hash: [0u8; 32],
+            from: vec![],
+            to: to.to_vec(),
+            value: vec![value],
+            fee: 0,
+            nonce: height,
+            chain_id,
             timestamp: get_timestamp(),
+            signature: vec![],
         };
 
-        block_manager.add_transaction(blockchain, tx.clone());
+        tx.hash = tx.compute_hash();
         tx
     }
 
-    pub fn to_string(&self) -> String {
-        to_hex(&self.hash)
+    pub fn is_coinbase(&self) -> bool {
+        self.signer.is_empty() && self.from.is_empty()
+    }
+
+    /// Whether this is a well-formed coinbase for the block at `height`
+    /// paying exactly `reward`.
+    pub fn is_valid_coinbase(&self, height: u64, reward: u64) -> bool {
+        self.is_coinbase()
+            && self.signature.is_empty()
+            && !self.to.is_empty()
+            && self.nonce == height
+            && self.fee == 0
+            && self.total_value() == Some(reward)
+            && self.hash == self.compute_hash()
+    }
+
+    fn compute_hash(&self) -> [u8; 32] {
+        Keccak256::hash(&self.signing_payload())
+    }
+
+    /// Canonical encoding of every field except the hash and the signature,
+    /// which are both derived from it.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        codec::encode(&(
+            &self.signer,
+            &self.from,
+            &self.to,
+            &self.value,
+            self.fee,
+            self.timestamp,
+            self.nonce,
+            self.chain_id,
+        ))
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        let Ok(public_key) = <&[u8; PUBLIC_KEY_LENGTH]>::try_from(self.signer.as_slice()) else {
+            return false;
+        };
+        let Ok(signature) = <&[u8; Signature::BYTE_SIZE]>::try_from(self.signature.as_slice())
+        else {
+            return false;
+        };
+
+        if self.from != public_key_to_address(public_key) || self.hash != self.compute_hash() {
+            return false;
+        }
+
+        verify(&self.signing_payload(), signature, public_key)
+    }
+
+    pub fn total_value(&self) -> Option<u64> {
+        self.value
+            .iter()
+            .try_fold(0u64, |total, value| total.checked_add(*value))
+    }
+
+    /// Everything the sender is debited: the value plus the fee.
+    pub fn total_cost(&self) -> Option<u64> {
+        self.total_value()?.checked_add(self.fee)
+    }
+
+    /// Size of the canonical encoding, which is what counts against the
+    /// block size limit.
+    pub fn size(&self) -> usize {
+        codec::encode(self).len()
+    }
+
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate {
+            fee: self.fee,
+            size: self.size() as u64,
+        }
+    }
+}
+
+/// Fee per encoded byte, compared by cross-multiplying so no precision is
+/// lost to division.
+#[derive(Debug, Clone, Copy)]
+pub struct FeeRate {
+    pub fee: u64,
+    pub size: u64,
+}
+
+impl Ord for FeeRate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fee as u128 * other.size as u128).cmp(&(other.fee as u128 * self.size as u128))
+    }
+}
+
+impl PartialOrd for FeeRate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FeeRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FeeRate {}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.hash))
     }
 }
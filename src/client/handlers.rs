@@ -1,10 +1,11 @@
 use crate::{
-    account::wallet::Wallet,
-    client::network::{broadcast_to_peers, to_hex, SharedState},
-    storage::ledger::LedgerValue,
+    account::{state::Account, wallet::Wallet},
+    chain::{block::TransactionProof, difficulty::DifficultyUpdate, transaction::Transaction},
+    client::network::{broadcast_to_peers, to_hex, transaction_message, SharedState},
+    storage::{ledger::LedgerValue, trie::PatriciaTrie},
+    utils::conversion::{from_hex, hex_to_hash},
 };
 use serde::{Deserialize, Serialize};
-use std::error::Error as StdError;
 use std::sync::Arc;
 use warp::{reject::Rejection, reply::Reply};
 
@@ -21,6 +22,137 @@ pub struct Response {
     pub tx_hash: Option<[u8; 32]>,
 }
 
+#[derive(Serialize)]
+pub struct AccountResponse {
+    pub success: bool,
+    pub message: String,
+    pub address: String,
+    pub account: Option<Account>,
+}
+
+#[derive(Serialize)]
+pub struct AccountProofResponse {
+    pub success: bool,
+    pub message: String,
+    pub state_root: Option<String>,
+    pub account: Option<Account>,
+    pub proof: Vec<String>,
+    pub verified: bool,
+}
+
+impl AccountProofResponse {
+    fn failure(message: String) -> Self {
+        Self {
+            success: false,
+            message,
+            state_root: None,
+            account: None,
+            proof: vec![],
+            verified: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AbsenceProofResponse {
+    pub success: bool,
+    pub message: String,
+    pub root: Option<String>,
+    pub proof: Option<String>,
+    pub verified: bool,
+}
+
+impl AbsenceProofResponse {
+    fn failure(message: String) -> Self {
+        Self {
+            success: false,
+            message,
+            root: None,
+            proof: None,
+            verified: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TransactionProofResponse {
+    pub success: bool,
+    pub message: String,
+    pub transactions_root: Option<String>,
+    pub proof: Option<TransactionProof>,
+    pub verified: bool,
+}
+
+impl TransactionProofResponse {
+    fn failure(message: String) -> Self {
+        Self {
+            success: false,
+            message,
+            transactions_root: None,
+            proof: None,
+            verified: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DifficultyRecord {
+    pub key: String,
+    pub update: DifficultyUpdate,
+    pub proof_indices: Vec<usize>,
+    pub proof: Option<String>,
+    pub verified: bool,
+}
+
+#[derive(Serialize)]
+pub struct DifficultyHistoryResponse {
+    pub success: bool,
+    pub message: String,
+    pub current_bits: u32,
+    pub root: Option<String>,
+    pub updates: Vec<DifficultyRecord>,
+}
+
+#[derive(Serialize)]
+pub struct MempoolEntry {
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: Vec<u64>,
+    pub fee: u64,
+    pub nonce: u64,
+    pub size: usize,
+}
+
+impl From<&Transaction> for MempoolEntry {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            hash: to_hex(&transaction.hash),
+            from: to_hex(&transaction.from),
+            to: to_hex(&transaction.to),
+            value: transaction.value.clone(),
+            fee: transaction.fee,
+            nonce: transaction.nonce,
+            size: transaction.size(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MempoolResponse {
+    pub success: bool,
+    pub message: String,
+    pub size: usize,
+    pub transactions: Vec<MempoolEntry>,
+}
+
+#[derive(Serialize)]
+pub struct MempoolTransactionResponse {
+    pub success: bool,
+    pub message: String,
+    pub transaction: Option<MempoolEntry>,
+}
+
 pub async fn process_connect_request(
     state: Arc<SharedState>,
     body: warp::hyper::body::Bytes,
@@ -73,7 +205,7 @@ pub async fn process_connect_request(
                 key,
                 LedgerValue::Accounts(wallet.clone()),
                 "accounts",
-                &mut *storage_guard,
+                storage_guard.as_mut(),
             )
             .await
     };
@@ -88,28 +220,6 @@ pub async fn process_connect_request(
     }
     println!("Successfully committed wallet to ledger locally");
 
-    let store_result: Result<(), Box<dyn StdError + Send + Sync>> = {
-        let mut storage_guard = state.storage.lock().await;
-
-        let formatted_value_for_storage = {
-            let mut ledger_guard = state.ledger.lock().await;
-            let value = ledger_guard
-                .entries
-                .get(&key)
-                .expect("Entry disappeared after commit!")
-                .value
-                .clone();
-            ledger_guard.format_entry_value(&key, &value)
-        };
-
-        storage_guard.store(&key, formatted_value_for_storage).await
-    };
-
-    match store_result {
-        Ok(_) => println!("Successfully stored wallet locally"),
-        Err(e) => eprintln!("Failed to store wallet locally after commit: {}", e),
-    }
-
     let formatted_entry_value_string = {
         let mut ledger_guard = state.ledger.lock().await;
 
@@ -119,7 +229,7 @@ pub async fn process_connect_request(
             .expect("Entry disappeared after commit!")
             .value
             .clone();
-        ledger_guard.format_entry_value(&key, &value)
+        ledger_guard.format_entry_value(&key, value)
     };
 
     let broadcast_payload_string = format!("accounts:{}", formatted_entry_value_string);
@@ -137,3 +247,301 @@ pub async fn process_connect_request(
         tx_hash: Some(key),
     }))
 }
+
+pub async fn process_account_request(
+    address: String,
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let address_bytes = match from_hex(&address) {
+        Ok(bytes) if !bytes.is_empty() => bytes,
+        _ => {
+            return Ok(warp::reply::json(&AccountResponse {
+                success: false,
+                message: format!("Invalid address: {}", address),
+                address,
+                account: None,
+            }));
+        }
+    };
+
+    let account = match state.blockchain.lock().await.get_account(&address_bytes) {
+        Ok(account) => account,
+        Err(e) => {
+            return Ok(warp::reply::json(&AccountResponse {
+                success: false,
+                message: format!("Failed to read account state: {}", e),
+                address,
+                account: None,
+            }));
+        }
+    };
+
+    Ok(warp::reply::json(&AccountResponse {
+        success: true,
+        message: "Account state retrieved".to_string(),
+        address: to_hex(&address_bytes),
+        account: Some(account),
+    }))
+}
+
+pub async fn process_account_proof_request(
+    address: String,
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let address_bytes = match from_hex(&address) {
+        Ok(bytes) if !bytes.is_empty() => bytes,
+        _ => {
+            return Ok(warp::reply::json(&AccountProofResponse::failure(format!(
+                "Invalid address: {}",
+                address
+            ))));
+        }
+    };
+
+    let (state_root, proof) = {
+        let mut blockchain = state.blockchain.lock().await;
+        let state_root = blockchain.state.root();
+        (state_root, blockchain.state.prove_account(&address_bytes))
+    };
+
+    let proof = match proof {
+        Ok(proof) => proof,
+        Err(e) => {
+            return Ok(warp::reply::json(&AccountProofResponse::failure(format!(
+                "Failed to build account proof: {}",
+                e
+            ))));
+        }
+    };
+
+    let verified = PatriciaTrie::verify_proof(state_root, &address_bytes, &proof);
+    let account = verified
+        .clone()
+        .flatten()
+        .and_then(|bytes| Account::decode(&bytes));
+    let message = if account.is_some() {
+        "Inclusion proof generated"
+    } else {
+        "Exclusion proof generated"
+    };
+
+    Ok(warp::reply::json(&AccountProofResponse {
+        success: true,
+        message: message.to_string(),
+        state_root: Some(to_hex(&state_root)),
+        account,
+        proof: proof.iter().map(|node| to_hex(node)).collect(),
+        verified: verified.is_some(),
+    }))
+}
+
+pub async fn process_absence_proof_request(
+    tree_identifier: String,
+    identifier: String,
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let ledger = state.ledger.lock().await;
+    let Some(root) = ledger.get_tree_root(&tree_identifier) else {
+        return Ok(warp::reply::json(&AbsenceProofResponse::failure(format!(
+            "Unknown tree: {}",
+            tree_identifier
+        ))));
+    };
+
+    let Some(key_bytes) = ledger.key_for(&tree_identifier, &identifier) else {
+        return Ok(warp::reply::json(&AbsenceProofResponse::failure(format!(
+            "Invalid identifier for tree '{}': {}",
+            tree_identifier, identifier
+        ))));
+    };
+
+    let Some(proof) = ledger.prove_absence(&tree_identifier, &key_bytes) else {
+        return Ok(warp::reply::json(&AbsenceProofResponse::failure(format!(
+            "{} is present in tree '{}' or the tree cannot prove absence",
+            identifier, tree_identifier
+        ))));
+    };
+
+    let verified = ledger.verify_absence(&tree_identifier, &key_bytes, &proof);
+
+    Ok(warp::reply::json(&AbsenceProofResponse {
+        success: true,
+        message: "Non-membership proof generated".to_string(),
+        root: Some(to_hex(&root)),
+        proof: Some(to_hex(&proof)),
+        verified,
+    }))
+}
+
+pub async fn process_transaction_proof_request(
+    block_hash: String,
+    transaction_hash: String,
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let (block_hash_bytes, transaction_hash_bytes) =
+        match (hex_to_hash(&block_hash), hex_to_hash(&transaction_hash)) {
+            (Some(block_hash), Some(transaction_hash)) => (block_hash, transaction_hash),
+            _ => {
+                return Ok(warp::reply::json(&TransactionProofResponse::failure(
+                    "Invalid block or transaction hash".to_string(),
+                )));
+            }
+        };
+
+    let block = state
+        .blockchain
+        .lock()
+        .await
+        .block_index
+        .get(&block_hash_bytes)
+        .map(|entry| entry.block.clone());
+
+    let Some(block) = block else {
+        return Ok(warp::reply::json(&TransactionProofResponse::failure(
+            format!("Block {} not found", block_hash),
+        )));
+    };
+
+    let Some(proof) = block.transaction_proof(&transaction_hash_bytes) else {
+        return Ok(warp::reply::json(&TransactionProofResponse::failure(
+            format!(
+                "Transaction {} is not included in block {}",
+                transaction_hash, block_hash
+            ),
+        )));
+    };
+
+    let verified = proof.verify(&block.header.transactions_root);
+
+    Ok(warp::reply::json(&TransactionProofResponse {
+        success: true,
+        message: "Inclusion proof generated".to_string(),
+        transactions_root: Some(to_hex(&block.header.transactions_root)),
+        proof: Some(proof),
+        verified,
+    }))
+}
+
+pub async fn process_difficulty_history_request(
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let current_bits = state.blockchain.lock().await.current_bits;
+
+    let ledger = state.ledger.lock().await;
+    let root = ledger.get_tree_root("mining");
+    let updates: Vec<DifficultyRecord> = ledger
+        .difficulty_history()
+        .into_iter()
+        .map(|(key, update)| {
+            let proof = ledger.prove_entry(&key);
+            DifficultyRecord {
+                key: to_hex(&key),
+                update,
+                proof_indices: proof
+                    .as_ref()
+                    .map_or(vec![], |proof| proof.proof_indices.clone()),
+                proof: proof.map(|proof| to_hex(&proof.proof_data)),
+                verified: ledger.verify_entry(&key),
+            }
+        })
+        .collect();
+
+    Ok(warp::reply::json(&DifficultyHistoryResponse {
+        success: true,
+        message: format!("{} difficulty updates recorded", updates.len()),
+        current_bits,
+        root: root.map(|root| to_hex(&root)),
+        updates,
+    }))
+}
+
+pub async fn process_transaction_submission(
+    state: Arc<SharedState>,
+    body: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    let transaction: Transaction = match serde_json::from_slice(&body) {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            return Ok(warp::reply::json(&Response {
+                success: false,
+                message: format!("Deserialize error: {}", e),
+                tx_hash: None,
+            }));
+        }
+    };
+
+    let hash = transaction.hash;
+    let message = transaction_message(&transaction);
+    if let Err(e) = state
+        .blockchain
+        .lock()
+        .await
+        .submit_transaction(transaction)
+    {
+        return Ok(warp::reply::json(&Response {
+            success: false,
+            message: format!("Transaction rejected: {}", e),
+            tx_hash: Some(hash),
+        }));
+    }
+
+    broadcast_to_peers(&state, message).await;
+    println!("Triggered broadcast for transaction: {}", to_hex(&hash));
+
+    Ok(warp::reply::json(&Response {
+        success: true,
+        message: format!("Transaction {} added to mempool", to_hex(&hash)),
+        tx_hash: Some(hash),
+    }))
+}
+
+pub async fn process_mempool_request(state: Arc<SharedState>) -> Result<impl Reply, Rejection> {
+    let blockchain = state.blockchain.lock().await;
+    let transactions: Vec<MempoolEntry> = blockchain
+        .mempool
+        .transactions()
+        .map(MempoolEntry::from)
+        .collect();
+
+    Ok(warp::reply::json(&MempoolResponse {
+        success: true,
+        message: format!("{} transactions pending", transactions.len()),
+        size: blockchain.mempool.size(),
+        transactions,
+    }))
+}
+
+pub async fn process_mempool_transaction_request(
+    hash: String,
+    state: Arc<SharedState>,
+) -> Result<impl Reply, Rejection> {
+    let Some(hash_bytes) = hex_to_hash(&hash) else {
+        return Ok(warp::reply::json(&MempoolTransactionResponse {
+            success: false,
+            message: format!("Invalid transaction hash: {}", hash),
+            transaction: None,
+        }));
+    };
+
+    let transaction = state
+        .blockchain
+        .lock()
+        .await
+        .mempool
+        .get(&hash_bytes)
+        .map(MempoolEntry::from);
+
+    let Some(transaction) = transaction else {
+        return Ok(warp::reply::json(&MempoolTransactionResponse {
+            success: false,
+            message: format!("Transaction {} is not pending", hash),
+            transaction: None,
+        }));
+    };
+
+    Ok(warp::reply::json(&MempoolTransactionResponse {
+        success: true,
+        message: "Pending transaction retrieved".to_string(),
+        transaction: Some(transaction),
+    }))
+}
@@ -1,7 +1,12 @@
 use std::sync::Arc;
 use warp::{Filter, Rejection, Reply};
 
-use crate::client::handlers::process_connect_request;
+use crate::client::handlers::{
+    process_absence_proof_request, process_account_proof_request, process_account_request,
+    process_connect_request, process_difficulty_history_request, process_mempool_request,
+    process_mempool_transaction_request, process_transaction_proof_request,
+    process_transaction_submission,
+};
 use crate::client::network::SharedState;
 
 pub fn create_connect_endpoint(
@@ -12,6 +17,88 @@ pub fn create_connect_endpoint(
         .and(warp::body::content_length_limit(1024 * 16))
         .and(warp::any().map(move || Arc::clone(&state)))
         .and(warp::body::bytes())
-        .and_then(|state, request| process_connect_request(state, request))
+        .and_then(process_connect_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_account_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "accounts" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_account_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_account_proof_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "accounts" / String / "proof")
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_account_proof_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_absence_proof_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "ledger" / String / "absence" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_absence_proof_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_transaction_proof_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "blocks" / String / "proofs" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_transaction_proof_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_difficulty_history_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "difficulty")
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_difficulty_history_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_transaction_submission_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "transactions")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and(warp::body::bytes())
+        .and_then(process_transaction_submission)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_mempool_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "mempool")
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_mempool_request)
+        .with(warp::cors().allow_any_origin())
+}
+
+pub fn create_mempool_transaction_endpoint(
+    state: Arc<SharedState>,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("api" / "mempool" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || Arc::clone(&state)))
+        .and_then(process_mempool_transaction_request)
         .with(warp::cors().allow_any_origin())
 }
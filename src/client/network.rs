@@ -7,18 +7,21 @@ use tokio::{
     time::{sleep, timeout},
 };
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
-use sha2::{Digest, Sha256}; 
 
 use crate::{
-    client::peer::receive_from_peer,
-    storage::{ledger::Ledger, level_db::Storage},
-    utils::{conversion::from_hex, encoding::{decode_base64_to_string, encode_string_to_base64}, env::get_listen_addr},
+    chain::{block::Block, blockchain::Blockchain, codec, transaction::Transaction},
+    client::peer::{receive_from_peer, PeerMessageOutcome},
+    cryptography::hash::{Hasher, Sha256},
+    storage::{backend::Storage, ledger::Ledger},
+    utils::{
+        conversion::{from_hex, hex_to_hash},
+        encoding::{decode_base64_to_string, encode_string_to_base64},
+        env::get_listen_addr,
+    },
 };
 
 fn calculate_message_id(payload: &Bytes) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(payload);
-    hasher.finalize().into()
+    Sha256::hash(payload)
 }
 
 pub fn to_hex(bytes: &[u8]) -> String {
@@ -29,8 +32,9 @@ pub fn to_hex(bytes: &[u8]) -> String {
 }
 
 pub struct SharedState {
+    pub blockchain: Arc<Mutex<Blockchain>>,
     pub ledger: Mutex<Ledger>,   
-    pub storage: Mutex<Storage>, 
+    pub storage: Mutex<Box<dyn Storage>>, 
     pub tx: broadcast::Sender<Bytes>,
     pub peers: RwLock<HashMap<SocketAddr, mpsc::Sender<Bytes>>>,
     pub seen_messages: Mutex<std::collections::HashSet<[u8; 32]>>,
@@ -59,6 +63,11 @@ async fn handle_connection(
         );
     } 
     
+    let (_, handshake_bytes, _) = encode_message(&handshake_message(&state).await);
+    if peer_tx.send(handshake_bytes).await.is_err() {
+        eprintln!("Failed to queue handshake for {}", peer_addr);
+    }
+
     let state_clone_for_broadcast = Arc::clone(&state);
     let state_clone_for_receive = Arc::clone(&state);
     let peer_addr_clone_for_send = peer_addr;
@@ -88,7 +97,7 @@ async fn handle_connection(
         loop {
             match broadcast_rx.recv().await {
                 Ok(msg_bytes_hex_encoded) => {
-                    if let Err(_) = peer_tx.send(msg_bytes_hex_encoded).await {
+                    if peer_tx.send(msg_bytes_hex_encoded).await.is_err() {
                         println!(
                             "Peer send channel closed for {}. Stopping broadcast forwarding.",
                             peer_addr_clone_for_broadcast
@@ -118,6 +127,7 @@ async fn handle_connection(
     });
     
     println!("Receive task started for {}", peer_addr_clone_for_receive);
+    let mut handshake_verified = false;
     let receive_result: Result<(), Box<dyn Error + Send + Sync>> = loop {
         let frame_result = reader.next().await;
 
@@ -189,10 +199,37 @@ async fn handle_connection(
                     Ok(base64_str) => match decode_base64_to_string(&base64_str) {
                         Ok(message) => {
                             println!("Decoded message content: {}", message); 
+
+                            if let Some(handshake) = message.strip_prefix("hello:") {
+                                match verify_handshake(&state_clone_for_receive, handshake).await {
+                                    Ok(()) => {
+                                        println!("Handshake with {} verified", peer_addr_clone_for_receive);
+                                        handshake_verified = true;
+                                        continue;
+                                    }
+                                    Err(e) => {
+                                        eprintln!("Handshake with {} failed: {}", peer_addr_clone_for_receive, e);
+                                        break Err(io::Error::new(
+                                            io::ErrorKind::PermissionDenied,
+                                            format!("Peer {} rejected: {}", peer_addr_clone_for_receive, e),
+                                        )
+                                        .into());
+                                    }
+                                }
+                            }
+
+                            if !handshake_verified {
+                                println!(
+                                    "Ignoring message from {} received before handshake",
+                                    peer_addr_clone_for_receive
+                                );
+                                continue;
+                            }
                             
                             if message.starts_with("blocks:")
                                 || message.starts_with("accounts:")
                                 || message.starts_with("mining:")
+                                || message.starts_with("transactions:")
                             {
                                 let parts: Vec<&str> = message.splitn(2, ':').collect();
                                 if parts.len() == 2 {
@@ -203,38 +240,15 @@ async fn handle_connection(
                                         continue; 
                                     }
                                     
-                                    let mut ledger = state_clone_for_receive.ledger.lock().await;
-                                    let mut storage = state_clone_for_receive.storage.lock().await;
-                                    let should_relay: bool;
-
-                                    match receive_from_peer(
-                                        decoded_inner_data, 
-                                        &mut ledger,
-                                        &mut storage,
+                                    let should_relay = process_ledger_message(
+                                        &state_clone_for_receive,
+                                        peer_addr_clone_for_receive,
+                                        &msg_id,
                                         identifier,
+                                        decoded_inner_data,
                                     )
-                                    .await
-                                    {
-                                        Ok(response) => {
-                                            println!(
-                                                "Processed unique message {} from {}. Response: {}",
-                                                to_hex(&msg_id), 
-                                                peer_addr_clone_for_receive,
-                                                response
-                                            );
-                                            should_relay = true; 
-                                        }
-                                        Err(e) => {
-                                            eprintln!(
-                                                "Error processing unique message {} from {}: {}",
-                                                to_hex(&msg_id), 
-                                                peer_addr_clone_for_receive,
-                                                e
-                                            );
-                                            should_relay = false;
-                                        }
-                                    } 
-                                    
+                                    .await;
+
                                     if should_relay {
                                         let peers_map = state_clone_for_receive.peers.read().await; 
                                         let recipients: Vec<mpsc::Sender<Bytes>> = peers_map
@@ -257,10 +271,7 @@ async fn handle_connection(
                                         for recipient_tx in recipients {
                                             let msg_clone = message_bytes_to_relay.clone(); 
                                             tokio::spawn(async move {
-                                                if let Err(_) =
-                                                    recipient_tx.send(msg_clone.into()).await
-                                                {
-                                                }
+                                                let _ = recipient_tx.send(msg_clone.into()).await;
                                             });
                                         }
                                     }
@@ -270,6 +281,26 @@ async fn handle_connection(
                                         peer_addr_clone_for_receive, message
                                     );
                                 }
+                            } else if let Some(reply) = message.strip_prefix("block:") {
+                                // Replies carry the request id so they never share a message id
+                                // with the original broadcast of the block, which we may have
+                                // seen before the block dropped out of the orphan pool.
+                                let block_hex = reply.split(':').next().unwrap_or_default();
+                                process_ledger_message(
+                                    &state_clone_for_receive,
+                                    peer_addr_clone_for_receive,
+                                    &msg_id,
+                                    "blocks",
+                                    block_hex.to_string(),
+                                )
+                                .await;
+                            } else if let Some(request) = message.strip_prefix("getblock:") {
+                                respond_to_block_request(
+                                    &state_clone_for_receive,
+                                    peer_addr_clone_for_receive,
+                                    request,
+                                )
+                                .await;
                             } else {
                                 println!(
                                     "Received non-ledger message from {}: {}",
@@ -298,8 +329,7 @@ async fn handle_connection(
                     peer_addr_clone_for_receive, e
                 );
                 
-                break Err(io::Error::new(
-                    io::ErrorKind::Other,
+                break Err(io::Error::other(
                     format!(
                         "Failed to read from peer {}: {}",
                         peer_addr_clone_for_receive, e
@@ -339,14 +369,173 @@ async fn handle_connection(
 
 
 
-pub async fn broadcast_to_peers(state: &Arc<SharedState>, payload_string: String) {
-    let base64_payload_bytes = Bytes::from(encode_string_to_base64(&payload_string).into_bytes());
+fn encode_message(payload_string: &str) -> ([u8; 32], Bytes, usize) {
+    let base64_payload_bytes = Bytes::from(encode_string_to_base64(payload_string).into_bytes());
     let msg_id = calculate_message_id(&base64_payload_bytes);
     let mut raw_message_bytes = Vec::with_capacity(32 + base64_payload_bytes.len());
     raw_message_bytes.extend_from_slice(&msg_id);
     raw_message_bytes.extend_from_slice(&base64_payload_bytes);
     let hex_encoded_message_string = to_hex(&raw_message_bytes);
-    let serialized_msg_bytes_hex_encoded = Bytes::from(hex_encoded_message_string.into_bytes()); 
+    (
+        msg_id,
+        Bytes::from(hex_encoded_message_string.into_bytes()),
+        base64_payload_bytes.len(),
+    )
+}
+
+async fn handshake_message(state: &Arc<SharedState>) -> String {
+    let blockchain = state.blockchain.lock().await;
+    let handshake_id: u64 = rand::random();
+    format!(
+        "hello:{}:{}:{}",
+        blockchain.chain_id,
+        to_hex(&blockchain.genesis_hash),
+        handshake_id
+    )
+}
+
+async fn verify_handshake(state: &Arc<SharedState>, handshake: &str) -> Result<(), String> {
+    let mut parts = handshake.split(':');
+    let chain_id = parts
+        .next()
+        .and_then(|chain_id| chain_id.parse::<u64>().ok())
+        .ok_or("missing chain id")?;
+    let genesis_hash = parts
+        .next()
+        .and_then(|genesis_hash| from_hex(genesis_hash).ok())
+        .ok_or("missing genesis hash")?;
+
+    let blockchain = state.blockchain.lock().await;
+    if chain_id != blockchain.chain_id {
+        return Err(format!(
+            "chain id {} does not match ours ({})",
+            chain_id, blockchain.chain_id
+        ));
+    }
+    if genesis_hash != blockchain.genesis_hash {
+        return Err(format!(
+            "genesis hash {} does not match ours ({})",
+            to_hex(&genesis_hash),
+            to_hex(&blockchain.genesis_hash)
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn send_to_peer(state: &Arc<SharedState>, peer_addr: SocketAddr, payload_string: String) {
+    let (msg_id, serialized_msg_bytes_hex_encoded, _) = encode_message(&payload_string);
+
+    let sender = state.peers.read().await.get(&peer_addr).cloned();
+    match sender {
+        Some(sender) => {
+            if sender.send(serialized_msg_bytes_hex_encoded).await.is_err() {
+                eprintln!("Failed to send message {} to {}", to_hex(&msg_id), peer_addr);
+            }
+        }
+        None => eprintln!("Peer {} is no longer connected", peer_addr),
+    }
+}
+
+/// Hands a ledger message to the chain and ledger, requesting the parent of
+/// orphan blocks from the sender. Returns whether the message should be relayed.
+async fn process_ledger_message(
+    state: &Arc<SharedState>,
+    peer_addr: SocketAddr,
+    msg_id: &[u8; 32],
+    identifier: &str,
+    data: String,
+) -> bool {
+    let mut blockchain = state.blockchain.lock().await;
+    let mut ledger = state.ledger.lock().await;
+    let mut storage = state.storage.lock().await;
+    let outcome = receive_from_peer(
+        data,
+        &mut blockchain,
+        &mut ledger,
+        storage.as_mut(),
+        identifier,
+    )
+    .await;
+    drop(storage);
+    drop(ledger);
+    drop(blockchain);
+
+    match outcome {
+        Ok(PeerMessageOutcome::Accepted(key)) => {
+            println!(
+                "Processed unique message {} from {}. Accepted key: {}",
+                to_hex(msg_id),
+                peer_addr,
+                to_hex(&key)
+            );
+            true
+        }
+        Ok(PeerMessageOutcome::MissingParent(missing)) => {
+            request_block(state, peer_addr, &missing).await;
+            false
+        }
+        Err(e) => {
+            eprintln!(
+                "Error processing unique message {} from {}: {}",
+                to_hex(msg_id),
+                peer_addr,
+                e
+            );
+            false
+        }
+    }
+}
+
+async fn request_block(state: &Arc<SharedState>, peer_addr: SocketAddr, hash: &[u8; 32]) {
+    println!("Requesting block {} from {}", to_hex(hash), peer_addr);
+    let request_id: u64 = rand::random();
+    send_to_peer(state, peer_addr, format!("getblock:{}:{}", to_hex(hash), request_id)).await;
+}
+
+async fn respond_to_block_request(state: &Arc<SharedState>, peer_addr: SocketAddr, request: &str) {
+    let hash_hex = request.split(':').next().unwrap_or_default();
+    let Some(hash) = hex_to_hash(hash_hex) else {
+        eprintln!("Invalid block request from {}: {}", peer_addr, hash_hex);
+        return;
+    };
+
+    let block = state
+        .blockchain
+        .lock()
+        .await
+        .block_index
+        .get(&hash)
+        .map(|entry| entry.block.clone());
+
+    let Some(block) = block else {
+        println!("Block {} requested by {} is unknown", hash_hex, peer_addr);
+        return;
+    };
+
+    // Echo the request id so the reply gets a message id of its own.
+    let request_id = request.split(':').nth(1).unwrap_or_default();
+    println!("Serving block {} to {}", hash_hex, peer_addr);
+    send_to_peer(state, peer_addr, block_reply_message(&block, request_id)).await;
+}
+
+/// Blocks travel in their canonical encoding, hex encoded.
+pub fn block_message(block: &Block) -> String {
+    format!("blocks:{}", to_hex(&codec::encode(block)))
+}
+
+/// Replies to `getblock` requests, tagged with the request they answer.
+fn block_reply_message(block: &Block, request_id: &str) -> String {
+    format!("block:{}:{}", to_hex(&codec::encode(block)), request_id)
+}
+
+/// Pooled transactions are gossiped the same way as blocks.
+pub fn transaction_message(transaction: &Transaction) -> String {
+    format!("transactions:{}", to_hex(&codec::encode(transaction)))
+}
+
+pub async fn broadcast_to_peers(state: &Arc<SharedState>, payload_string: String) {
+    let (msg_id, serialized_msg_bytes_hex_encoded, payload_len) = encode_message(&payload_string);
 
     let is_seen = {
         let mut seen_messages = state.seen_messages.lock().await; 
@@ -369,7 +558,7 @@ pub async fn broadcast_to_peers(state: &Arc<SharedState>, payload_string: String
     println!(
         "Broadcasting locally originated message {} ({} bytes payload, {} hex bytes) to {} subscribers via channel",
         to_hex(&msg_id), 
-        base64_payload_bytes.len(), 
+        payload_len, 
         serialized_msg_bytes_hex_encoded.len(), 
         receiver_count
     );
@@ -1,18 +1,117 @@
 use crate::{
+    chain::{
+        block::Block,
+        blockchain::{Blockchain, BlockchainError},
+        codec,
+        transaction::Transaction,
+    },
     storage::{
-        ledger::{DeserializedLedgerValue, Ledger},
-        level_db::Storage,
+        backend::Storage,
+        ledger::{DeserializedLedgerValue, Ledger, LedgerValue},
     },
-    utils::conversion::to_hex,
+    utils::conversion::{from_hex, to_hex},
 };
 use std::error::Error;
 
+pub enum PeerMessageOutcome {
+    Accepted([u8; 32]),
+    MissingParent([u8; 32]),
+}
+
+async fn process_peer_block(
+    blockchain: &mut Blockchain,
+    ledger: &mut Ledger,
+    storage: &mut dyn Storage,
+    block: Block,
+) -> Result<PeerMessageOutcome, Box<dyn Error + Send + Sync>> {
+    let height = block.header.height;
+    let hash = block.hash;
+    let key = ledger.get_key(&LedgerValue::Blocks(block.clone()));
+
+    let outcome = match blockchain.add_block(block) {
+        Ok(outcome) => outcome,
+        Err(BlockchainError::OrphanBlock(missing)) => {
+            println!(
+                "Block {} is an orphan, waiting for ancestor {}",
+                height,
+                to_hex(&missing)
+            );
+            return Ok(PeerMessageOutcome::MissingParent(missing));
+        }
+        Err(e) => return Err(format!("Block {} rejected by chain: {}", height, e).into()),
+    };
+
+    println!("Block {} accepted by chain: {:?}", height, outcome);
+
+    let mut outcomes = vec![outcome];
+    outcomes.extend(blockchain.connect_orphans(&hash));
+
+    // Orphans connected above are already part of the chain, so every
+    // outcome is applied even after one fails.
+    let mut failed = 0;
+    for outcome in outcomes {
+        let tip = outcome
+            .connected_blocks()
+            .last()
+            .map(|block| block.header.height);
+        if ledger.apply_block_outcome(outcome, storage).await.is_none() {
+            failed += 1;
+            match tip {
+                Some(tip) => eprintln!("Failed to commit outcome up to block {} to ledger", tip),
+                None => eprintln!("Failed to commit side branch outcome to ledger"),
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!(
+            "Failed to commit {} outcomes of block {} to ledger",
+            failed, height
+        )
+        .into());
+    }
+
+    Ok(PeerMessageOutcome::Accepted(key))
+}
+
+fn process_peer_transaction(
+    blockchain: &mut Blockchain,
+    transaction: Transaction,
+) -> Result<PeerMessageOutcome, Box<dyn Error + Send + Sync>> {
+    let hash = transaction.hash;
+    blockchain
+        .submit_transaction(transaction)
+        .map_err(|e| format!("Transaction {} rejected by mempool: {}", to_hex(&hash), e))?;
+
+    println!(
+        "Transaction {} added to mempool ({} pooled)",
+        to_hex(&hash),
+        blockchain.mempool.len()
+    );
+    Ok(PeerMessageOutcome::Accepted(hash))
+}
+
 async fn process_peer_state(
+    blockchain: &mut Blockchain,
     ledger: &mut Ledger,
-    storage: &mut Storage,
+    storage: &mut dyn Storage,
     data: String,
     identifier: &str,
-) -> Result<[u8; 32], Box<dyn Error + Send + Sync>> {
+) -> Result<PeerMessageOutcome, Box<dyn Error + Send + Sync>> {
+    if identifier == "blocks" {
+        let bytes = from_hex(&data)?;
+        let block: Block = codec::decode(&bytes)
+            .map_err(|e| format!("Failed to decode block from peer: {}", e))?;
+        return process_peer_block(blockchain, ledger, storage, block).await;
+    }
+
+    if identifier == "transactions" {
+        let bytes = from_hex(&data)?;
+        let transaction: Transaction = codec::decode(&bytes)
+            .map_err(|e| format!("Failed to decode transaction from peer: {}", e))?;
+        return process_peer_transaction(blockchain, transaction);
+    }
+
     let new_state: DeserializedLedgerValue =
         match serde_json::from_str::<DeserializedLedgerValue>(&data) {
             Ok(value) => value,
@@ -25,6 +124,14 @@ async fn process_peer_state(
             }
         };
 
+    if matches!(new_state.value, LedgerValue::Blocks(_)) {
+        return Err("Blocks must be sent in their canonical encoding".into());
+    }
+
+    if matches!(new_state.value, LedgerValue::Mining(_)) {
+        return Err("Difficulty updates are derived from blocks, not accepted from peers".into());
+    }
+
     let calculated_key = ledger.get_key(&new_state.value);
     if ledger.entries.contains_key(&calculated_key) {
         return Err(format!(
@@ -51,7 +158,7 @@ async fn process_peer_state(
                 identifier,
                 to_hex(&calculated_key)
             );
-            Ok(calculated_key)
+            Ok(PeerMessageOutcome::Accepted(calculated_key))
         }
         None => {
             eprintln!(
@@ -65,24 +172,30 @@ async fn process_peer_state(
 }
 
 async fn handle_peer_message(
+    blockchain: &mut Blockchain,
     ledger: &mut Ledger,
-    storage: &mut Storage,
+    storage: &mut dyn Storage,
     decoded_inner_data: String,
     identifier: &str,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    match process_peer_state(ledger, storage, decoded_inner_data, identifier).await {
-        Ok(key) => Ok(format!("Data ({}) accepted: {}", identifier, to_hex(&key))),
+) -> Result<PeerMessageOutcome, Box<dyn Error + Send + Sync>> {
+    match process_peer_state(blockchain, ledger, storage, decoded_inner_data, identifier).await {
+        Ok(PeerMessageOutcome::Accepted(key)) => {
+            println!("Data ({}) accepted: {}", identifier, to_hex(&key));
+            Ok(PeerMessageOutcome::Accepted(key))
+        }
+        Ok(outcome) => Ok(outcome),
         Err(e) => Err(format!("Rejected {} state: {}", identifier, e).into()),
     }
 }
 
 pub async fn receive_from_peer(
     decoded_inner_data: String,
+    blockchain: &mut Blockchain,
     ledger: &mut Ledger,
-    storage: &mut Storage,
+    storage: &mut dyn Storage,
     identifier: &str,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
+) -> Result<PeerMessageOutcome, Box<dyn Error + Send + Sync>> {
     println!("Received message from peer: {}", decoded_inner_data);
 
-    handle_peer_message(ledger, storage, decoded_inner_data, identifier).await
+    handle_peer_message(blockchain, ledger, storage, decoded_inner_data, identifier).await
 }
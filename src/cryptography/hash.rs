@@ -1,22 +1,24 @@
 use keccak_asm::Digest;
-use keccak_asm::Keccak256;
 
-use crate::utils::conversion::to_hex;
+/// Hash function over raw bytes with a 32-byte digest.
+pub trait Hasher {
+    fn hash(input: &[u8]) -> [u8; 32];
+}
+
+/// Used for block, transaction and ledger key hashes.
+pub struct Keccak256;
 
-pub fn transform(input: &String) -> String {
-    let mut keccak = Keccak256::new();
-    keccak.update(input.as_bytes());
-    let result = keccak.finalize();
-    to_hex(&result)
+impl Hasher for Keccak256 {
+    fn hash(input: &[u8]) -> [u8; 32] {
+        keccak_asm::Keccak256::digest(input).into()
+    }
 }
 
-pub fn verify(input: &String, hash: String) -> bool {
-    let mut keccak = Keccak256::new();
-    keccak.update(input.as_bytes());
-    let result = keccak.finalize();
-    let hex_hash = result
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>();
-    hex_hash == hash
+/// Used for peer message ids.
+pub struct Sha256;
+
+impl Hasher for Sha256 {
+    fn hash(input: &[u8]) -> [u8; 32] {
+        sha2::Sha256::digest(input).into()
+    }
 }
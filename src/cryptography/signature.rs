@@ -6,12 +6,13 @@ use rand::Rng;
 
 pub fn get_private_key(private_key: Option<&[u8; SECRET_KEY_LENGTH]>) -> SigningKey {
     let seed = match private_key {
-        Some(private_key) => private_key.clone(),
+        Some(private_key) => *private_key,
         None => rand::rng().random::<[u8; SECRET_KEY_LENGTH]>(),
     };
     SigningKey::from_bytes(&seed)
 }
 
+#[allow(dead_code)]
 pub fn generate_keypair(private_key: Option<&[u8; SECRET_KEY_LENGTH]>) -> (Vec<u8>, Vec<u8>) {
     let signing_key = get_private_key(private_key);
     let verifying_key = signing_key.verifying_key();
@@ -31,7 +32,10 @@ pub fn verify(
     signature: &[u8; Signature::BYTE_SIZE],
     public_key: &[u8; PUBLIC_KEY_LENGTH],
 ) -> bool {
-    let verifying_key = VerifyingKey::from_bytes(public_key).unwrap();
+    let verifying_key = match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
     let signature = Signature::from_bytes(signature);
     verifying_key.verify_strict(message, &signature).is_ok()
 }
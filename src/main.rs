@@ -3,6 +3,7 @@ use chain::blockchain::Blockchain;
 use client::network::connect_to_peers;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{broadcast, Mutex, RwLock};
+use warp::Filter;
 
 mod account;
 mod chain;
@@ -14,19 +15,62 @@ mod utils;
 #[tokio::main]
 async fn main() {
     let (broadcast_tx, _) = broadcast::channel::<Bytes>(100);
+    let chain_spec = chain::spec::ChainSpec::from_env();
+    let chain_store = storage::chain_store::ChainStore::new(storage::backend::open_storage(
+        &utils::env::get_chain_database_path(),
+    ));
+    let blockchain = Arc::new(Mutex::new(
+        chain::blockchain::Blockchain::load(
+            &chain_spec,
+            chain_store,
+            storage::backend::open_storage(&utils::env::get_state_database_path()),
+        )
+        .unwrap_or_else(|e| panic!("Failed to load blockchain from disk: {}", e)),
+    ));
+    let mut ledger_storage = storage::backend::open_storage(&utils::env::get_database_path());
+    let ledger_commitments = utils::env::get_ledger_commitments();
+    let mut ledger = storage::ledger::Ledger::load(ledger_storage.as_mut(), &ledger_commitments)
+        .unwrap_or_else(|e| panic!("Failed to recover ledger from disk: {}", e));
+    if ledger
+        .catch_up(&*blockchain.lock().await, ledger_storage.as_mut())
+        .await
+        .is_none()
+    {
+        panic!("Failed to replay chain outcomes missing from the ledger");
+    }
     let state = Arc::new(client::network::SharedState {
-        ledger: Mutex::new(storage::ledger::Ledger::new()),
-        storage: Mutex::new(storage::level_db::Storage::new(
-            &utils::env::get_database_path(),
-        )),
+        blockchain: Arc::clone(&blockchain),
+        ledger: Mutex::new(ledger),
+        storage: Mutex::new(ledger_storage),
         tx: broadcast_tx,
         peers: RwLock::new(HashMap::new()),
         seen_messages: Mutex::new(std::collections::HashSet::new()),
     });
 
-    let routes = client::http::create_connect_endpoint(Arc::clone(&state));
-    let blockchain = Arc::new(Mutex::new(chain::blockchain::Blockchain::new(0)));
-    let block_manager = Arc::new(Mutex::new(chain::block_manager::BlockManager::new(500)));
+    let routes = client::http::create_connect_endpoint(Arc::clone(&state))
+        .or(client::http::create_account_endpoint(Arc::clone(&state)))
+        .or(client::http::create_account_proof_endpoint(Arc::clone(
+            &state,
+        )))
+        .or(client::http::create_absence_proof_endpoint(Arc::clone(
+            &state,
+        )))
+        .or(client::http::create_transaction_proof_endpoint(Arc::clone(
+            &state,
+        )))
+        .or(client::http::create_difficulty_history_endpoint(
+            Arc::clone(&state),
+        ))
+        .or(client::http::create_transaction_submission_endpoint(
+            Arc::clone(&state),
+        ))
+        .or(client::http::create_mempool_endpoint(Arc::clone(&state)))
+        .or(client::http::create_mempool_transaction_endpoint(
+            Arc::clone(&state),
+        ));
+    let block_manager = Arc::new(Mutex::new(chain::block_manager::BlockManager::from_env(
+        chain_spec.target_block_time,
+    )));
 
     tokio::spawn(async move {
         let addr = utils::env::get_api_addr();
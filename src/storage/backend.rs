@@ -0,0 +1,96 @@
+use std::fmt;
+
+use crate::storage::{level_db::LevelDbStorage, memory::MemoryStorage};
+use crate::utils::env::get_storage_backend;
+
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug)]
+pub enum StorageError {
+    Write(String),
+    #[allow(dead_code)]
+    Delete(String),
+    Batch(String),
+    Iterator(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Write(e) => write!(f, "failed to write key: {}", e),
+            StorageError::Delete(e) => write!(f, "failed to delete key: {}", e),
+            StorageError::Batch(e) => write!(f, "failed to apply write batch: {}", e),
+            StorageError::Iterator(e) => write!(f, "failed to create iterator: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[derive(Debug, Clone)]
+pub enum BatchOperation {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Set of puts and deletes applied to the backend in a single atomic write.
+#[derive(Debug, Clone, Default)]
+pub struct StorageBatch {
+    operations: Vec<BatchOperation>,
+}
+
+impl StorageBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.operations
+            .push(BatchOperation::Put(key.to_vec(), value.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.operations.push(BatchOperation::Delete(key.to_vec()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn into_operations(self) -> Vec<BatchOperation> {
+        self.operations
+    }
+}
+
+pub trait Storage: Send {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    #[allow(dead_code)]
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError>;
+
+    /// Applies every operation of the batch or none of them. Backends that
+    /// persist must have the batch durable before returning.
+    fn write_batch(&mut self, batch: StorageBatch) -> Result<(), StorageError>;
+
+    /// Returns every key/value pair whose key starts with `prefix`, in key order.
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Vec<KeyValue>, StorageError>;
+
+    /// Returns every key/value pair with `start <= key < end`, in key order.
+    #[allow(dead_code)]
+    fn scan_range(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<KeyValue>, StorageError>;
+}
+
+/// Opens the backend selected by `STORAGE_BACKEND`. The in-memory backend
+/// ignores `path` and loses everything when the node stops.
+pub fn open_storage(path: &str) -> Box<dyn Storage> {
+    match get_storage_backend().as_str() {
+        "memory" => {
+            println!("Using in-memory storage in place of {}", path);
+            Box::new(MemoryStorage::new())
+        }
+        "leveldb" => Box::new(LevelDbStorage::new(path)),
+        other => panic!("Unknown storage backend: {}", other),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::chain::{block::Block, codec};
+use crate::storage::backend::{Storage, StorageBatch, StorageError};
+
+const BLOCK_PREFIX: &[u8] = b"block:";
+const HEIGHT_PREFIX: &[u8] = b"height:";
+const TIP_KEY: &[u8] = b"tip";
+
+pub struct ChainStore {
+    storage: Box<dyn Storage>,
+}
+
+impl ChainStore {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    fn block_key(hash: &[u8]) -> Vec<u8> {
+        [BLOCK_PREFIX, hash].concat()
+    }
+
+    fn height_key(height: u64) -> Vec<u8> {
+        [HEIGHT_PREFIX, &height.to_be_bytes()].concat()
+    }
+
+    pub fn put_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        self.storage
+            .put(&Self::block_key(&block.hash), &codec::encode(block))
+    }
+
+    /// Stores a block joining the main chain together with its height index
+    /// and the tip, so the tip never points at a block that is not stored.
+    pub fn set_main_chain_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let mut batch = StorageBatch::new();
+        batch.put(&Self::block_key(&block.hash), &codec::encode(block));
+        batch.put(&Self::height_key(block.header.height), &block.hash);
+        batch.put(TIP_KEY, &block.hash);
+        self.storage.write_batch(batch)
+    }
+
+    /// Drops the height index of a block leaving the main chain and moves the
+    /// tip back to its parent in the same write.
+    pub fn unset_main_chain_block(
+        &mut self,
+        block: &Block,
+        new_tip: &Block,
+    ) -> Result<(), StorageError> {
+        let mut batch = StorageBatch::new();
+        batch.delete(&Self::height_key(block.header.height));
+        batch.put(TIP_KEY, &new_tip.hash);
+        self.storage.write_batch(batch)
+    }
+
+    pub fn get_block(&mut self, hash: &[u8]) -> Option<Block> {
+        let value = self.storage.get(&Self::block_key(hash))?;
+        match codec::decode(&value) {
+            Ok(block) => Some(block),
+            Err(e) => {
+                eprintln!("Failed to decode stored block: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn get_hash_by_height(&mut self, height: u64) -> Option<Vec<u8>> {
+        self.storage.get(&Self::height_key(height))
+    }
+
+    pub fn get_tip(&mut self) -> Option<Vec<u8>> {
+        self.storage.get(TIP_KEY)
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use crate::cryptography::hash::{Hasher, Keccak256};
+use crate::storage::{sparse_tree::SparseMerkleTree, tree::Tree};
+
+#[derive(Debug, Clone)]
+pub struct CommitmentProof {
+    pub indices: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+/// Authenticated map from ledger keys to the hash of their values. `Ledger`
+/// keeps one per named tree and only relies on this interface, so schemes
+/// can be swapped through configuration.
+pub trait StateCommitment: Send {
+    fn contains(&self, key: &[u8; 32]) -> bool;
+
+    /// Adds a new key and returns the proof to store with its entry, or
+    /// `None` when the new root did not verify and the insert was undone.
+    fn insert(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof>;
+
+    /// Replaces the value committed for a key that is already present.
+    fn update(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof>;
+
+    /// Undoes the last insert or update.
+    fn rollback(&mut self);
+
+    /// Keys in the order they were first inserted.
+    fn keys(&self) -> &[[u8; 32]];
+
+    fn len(&self) -> usize;
+
+    /// Current root. While nothing is committed this is the scheme's empty
+    /// root: zeroed for the Merkle scheme, and for the sparse scheme the
+    /// hash of a tree whose 2^256 leaves are all empty.
+    fn root(&self) -> [u8; 32];
+
+    fn prove(&self, key: &[u8; 32]) -> Option<CommitmentProof>;
+
+    fn verify(&self, key: &[u8; 32], value: &[u8; 32], proof: &CommitmentProof) -> bool;
+
+    /// Proof that `key` is not committed, for schemes that can show it.
+    fn prove_absence(&self, _key: &[u8; 32]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn verify_absence(&self, _key: &[u8; 32], _proof_bytes: &[u8]) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitmentKind {
+    Merkle,
+    Sparse,
+}
+
+impl CommitmentKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "merkle" => Some(CommitmentKind::Merkle),
+            "sparse" => Some(CommitmentKind::Sparse),
+            _ => None,
+        }
+    }
+
+    /// Builds a commitment holding `entries`, in insertion order.
+    pub fn build(
+        &self,
+        identifier: &str,
+        entries: &[([u8; 32], [u8; 32])],
+    ) -> Box<dyn StateCommitment> {
+        match self {
+            CommitmentKind::Merkle => Box::new(MerkleCommitment::from_entries(identifier, entries)),
+            CommitmentKind::Sparse => Box::new(SparseMerkleTree::from_entries(
+                identifier.to_string(),
+                entries,
+            )),
+        }
+    }
+}
+
+enum MerkleChange {
+    Inserted,
+    Updated { index: usize, previous: [u8; 32] },
+}
+
+/// Append-only rs_merkle tree whose leaves hash a key together with the
+/// hash of its value.
+pub struct MerkleCommitment {
+    tree: Tree,
+    identifier: String,
+    keys: Vec<[u8; 32]>,
+    positions: HashMap<[u8; 32], usize>,
+    last_change: Option<MerkleChange>,
+}
+
+impl MerkleCommitment {
+    pub fn from_entries(identifier: &str, entries: &[([u8; 32], [u8; 32])]) -> Self {
+        let leaves: Vec<[u8; 32]> = entries
+            .iter()
+            .map(|(key, value)| Self::leaf(key, value))
+            .collect();
+        Self {
+            tree: Tree::from_leaves(identifier.to_string(), &leaves),
+            identifier: identifier.to_string(),
+            keys: entries.iter().map(|(key, _)| *key).collect(),
+            positions: entries
+                .iter()
+                .enumerate()
+                .map(|(index, (key, _))| (*key, index))
+                .collect(),
+            last_change: None,
+        }
+    }
+
+    fn leaf(key: &[u8; 32], value: &[u8; 32]) -> [u8; 32] {
+        Keccak256::hash(&[key.as_slice(), value.as_slice()].concat())
+    }
+
+    fn proof_for(&self, index: usize) -> CommitmentProof {
+        CommitmentProof {
+            indices: vec![index],
+            data: self.tree.generate_proof_bytes(&[index]),
+        }
+    }
+}
+
+impl StateCommitment for MerkleCommitment {
+    fn contains(&self, key: &[u8; 32]) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    fn insert(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof> {
+        if self.contains(&key) {
+            return None;
+        }
+        self.tree.insert(Self::leaf(&key, &value));
+        let (ok, data, indices) = self.tree.commit();
+        if !ok {
+            return None;
+        }
+        self.positions.insert(key, self.keys.len());
+        self.keys.push(key);
+        self.last_change = Some(MerkleChange::Inserted);
+        Some(CommitmentProof { indices, data })
+    }
+
+    fn update(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof> {
+        let index = *self.positions.get(&key)?;
+        let previous = self.tree.set_leaf(index, Self::leaf(&key, &value))?;
+        self.last_change = Some(MerkleChange::Updated { index, previous });
+        Some(self.proof_for(index))
+    }
+
+    fn rollback(&mut self) {
+        match self.last_change.take() {
+            Some(MerkleChange::Inserted) => {
+                self.tree.rollback();
+                if let Some(key) = self.keys.pop() {
+                    self.positions.remove(&key);
+                }
+            }
+            Some(MerkleChange::Updated { index, previous }) => {
+                self.tree.set_leaf(index, previous);
+                println!("Tree '{}': Rolled back.", self.identifier);
+            }
+            None => {}
+        }
+    }
+
+    fn keys(&self) -> &[[u8; 32]] {
+        &self.keys
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn root(&self) -> [u8; 32] {
+        self.tree.get_root().unwrap_or_default()
+    }
+
+    fn prove(&self, key: &[u8; 32]) -> Option<CommitmentProof> {
+        self.positions.get(key).map(|index| self.proof_for(*index))
+    }
+
+    fn verify(&self, key: &[u8; 32], value: &[u8; 32], proof: &CommitmentProof) -> bool {
+        let Some(position) = self.positions.get(key) else {
+            return false;
+        };
+        let leaves = self.tree.get_leaves();
+        let mut proven = Vec::with_capacity(proof.indices.len());
+        for index in &proof.indices {
+            match leaves.get(*index) {
+                Some(_) if index == position => proven.push(Self::leaf(key, value)),
+                Some(leaf) => proven.push(*leaf),
+                None => return false,
+            }
+        }
+        proof.indices.contains(position)
+            && Tree::verify_proof_with_root(
+                self.root(),
+                &proven,
+                &proof.indices,
+                &proof.data,
+                leaves.len(),
+            )
+    }
+}
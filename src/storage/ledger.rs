@@ -1,13 +1,23 @@
 use crate::{
     account::wallet::Wallet,
-    chain::block::Block,
-    cryptography::hash::transform,
-    storage::{level_db::Storage, tree::Tree},
-    utils::conversion::{hash_to_32bit_array, to_hex},
+    chain::{
+        block::Block,
+        blockchain::{BlockOutcome, Blockchain},
+        codec,
+        difficulty::DifficultyUpdate,
+    },
+    cryptography::hash::{Hasher, Keccak256},
+    storage::{
+        backend::{Storage, StorageBatch, StorageError},
+        commitment::{CommitmentKind, CommitmentProof, StateCommitment},
+    },
+    utils::conversion::{from_hex, hex_to_hash, to_hex},
 };
+use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct LedgerEntry {
     pub key: [u8; 32],
@@ -16,6 +26,7 @@ pub struct LedgerEntry {
     pub version: u64,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct LedgerProof {
     pub tree_identifier: String,
@@ -23,20 +34,45 @@ pub struct LedgerProof {
     pub proof_data: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct DifficultyUpdate {
-    pub current: u64,
-    pub previous: u64,
-    pub difference: u64,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, Encode, Decode)]
 pub enum LedgerValue {
     Mining(DifficultyUpdate),
     Accounts(Wallet),
     Blocks(Block),
 }
 
+const MINING_KEY_TAG: u8 = 0x01;
+const ACCOUNTS_KEY_TAG: u8 = 0x02;
+const BLOCKS_KEY_TAG: u8 = 0x03;
+
+impl LedgerValue {
+    /// Canonical encoding of what an entry is identified by: a one-byte tag
+    /// for its kind followed by the big-endian height of a difficulty update,
+    /// the address of an account or the hash of a block. Other fields can
+    /// change without moving the entry to a new key.
+    pub fn key_bytes(&self) -> Vec<u8> {
+        match self {
+            LedgerValue::Mining(update) => Self::mining_key_bytes(update.height),
+            LedgerValue::Accounts(wallet) => Self::accounts_key_bytes(&wallet.address),
+            LedgerValue::Blocks(block) => Self::blocks_key_bytes(&block.hash),
+        }
+    }
+
+    fn mining_key_bytes(height: u64) -> Vec<u8> {
+        [[MINING_KEY_TAG].as_slice(), &height.to_be_bytes()].concat()
+    }
+
+    fn accounts_key_bytes(address: &[u8]) -> Vec<u8> {
+        [[ACCOUNTS_KEY_TAG].as_slice(), address].concat()
+    }
+
+    fn blocks_key_bytes(hash: &[u8; 32]) -> Vec<u8> {
+        [[BLOCKS_KEY_TAG].as_slice(), hash].concat()
+    }
+}
+
+/// An entry as gossiped to peers, see `format_entry_value`.
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct DeserializedLedgerValue {
     pub value: LedgerValue,
@@ -44,108 +80,536 @@ pub struct DeserializedLedgerValue {
     pub key: String,
 }
 
+/// An entry as persisted under its key, in the canonical encoding.
+#[derive(Debug, Encode, Decode)]
+struct EntryRecord {
+    key: [u8; 32],
+    value: LedgerValue,
+    version: u64,
+}
+
+/// Persisted alongside every commit so a restarted node can check the trees
+/// it rebuilds against the roots it last wrote. Stored in the canonical
+/// encoding, like the entries.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct TreeRootRecord {
+    pub root: [u8; 32],
+    pub leaf_count: u64,
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    Storage(StorageError),
+    MissingEntry(String),
+    CorruptEntry(String),
+    RootMismatch(String),
+    Config(String),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Storage(e) => write!(f, "storage error: {}", e),
+            LedgerError::MissingEntry(key) => write!(f, "indexed entry {} is missing", key),
+            LedgerError::CorruptEntry(key) => write!(f, "entry {} could not be decoded", key),
+            LedgerError::RootMismatch(tree) => {
+                write!(
+                    f,
+                    "rebuilt root of tree '{}' does not match the stored root",
+                    tree
+                )
+            }
+            LedgerError::Config(e) => write!(f, "invalid commitment configuration: {}", e),
+        }
+    }
+}
+
+/// Trees the node itself commits to; each must have a configured commitment.
+const TREE_IDENTIFIERS: [&str; 3] = ["mining", "accounts", "blocks"];
+const INDEX_PREFIX: &str = "ledger:index:";
+const ROOT_PREFIX: &str = "ledger:root:";
+
+struct NamedCommitment {
+    kind: CommitmentKind,
+    commitment: Box<dyn StateCommitment>,
+}
+
 pub struct Ledger {
-    pub mining_tree: Tree,
-    pub accounts_tree: Tree,
-    pub blocks_tree: Tree,
+    commitments: HashMap<String, NamedCommitment>,
     pub entries: HashMap<[u8; 32], LedgerEntry>,
 }
 
 impl Ledger {
-    pub fn new() -> Self {
-        Ledger {
-            mining_tree: Tree::new("mining".to_string()),
-            accounts_tree: Tree::new("accounts".to_string()),
-            blocks_tree: Tree::new("blocks".to_string()),
+    /// Creates an empty commitment for every `(tree, scheme)` pair, e.g.
+    /// `("accounts", "sparse")`.
+    pub fn new(config: &[(String, String)]) -> Result<Self, LedgerError> {
+        let mut commitments = HashMap::new();
+        for (tree_identifier, scheme) in config {
+            let kind = CommitmentKind::parse(scheme).ok_or_else(|| {
+                LedgerError::Config(format!(
+                    "unknown scheme '{}' for tree '{}'",
+                    scheme, tree_identifier
+                ))
+            })?;
+            commitments.insert(
+                tree_identifier.clone(),
+                NamedCommitment {
+                    kind,
+                    commitment: kind.build(tree_identifier, &[]),
+                },
+            );
+        }
+
+        if let Some(missing) = TREE_IDENTIFIERS
+            .iter()
+            .find(|tree_identifier| !commitments.contains_key(**tree_identifier))
+        {
+            return Err(LedgerError::Config(format!(
+                "no commitment configured for tree '{}'",
+                missing
+            )));
+        }
+
+        Ok(Ledger {
+            commitments,
             entries: HashMap::new(),
+        })
+    }
+
+    /// Rebuilds every tree from its index records, in the order the keys
+    /// were committed, and reloads the entries they point to. Each rebuilt
+    /// root is checked against the root record written with the last commit.
+    pub fn load(
+        storage: &mut dyn Storage,
+        config: &[(String, String)],
+    ) -> Result<Self, LedgerError> {
+        let mut ledger = Self::new(config)?;
+        for (tree_identifier, _) in config {
+            let recovered = ledger.recover_tree(tree_identifier, storage)?;
+            println!(
+                "Recovered {} entries for tree '{}'",
+                recovered, tree_identifier
+            );
+        }
+        Ok(ledger)
+    }
+
+    fn recover_tree(
+        &mut self,
+        tree_identifier: &str,
+        storage: &mut dyn Storage,
+    ) -> Result<usize, LedgerError> {
+        let index_prefix = format!("{}{}:", INDEX_PREFIX, tree_identifier);
+        let keys: Vec<[u8; 32]> = storage
+            .scan_prefix(index_prefix.as_bytes())
+            .map_err(LedgerError::Storage)?
+            .into_iter()
+            .map(|(_, key)| {
+                key.as_slice()
+                    .try_into()
+                    .map_err(|_| LedgerError::CorruptEntry(to_hex(&key)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut records = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let value = storage
+                .get(key)
+                .ok_or_else(|| LedgerError::MissingEntry(to_hex(key)))?;
+            let record: EntryRecord =
+                codec::decode(&value).map_err(|_| LedgerError::CorruptEntry(to_hex(key)))?;
+            if record.key != *key {
+                return Err(LedgerError::CorruptEntry(to_hex(key)));
+            }
+            records.push(record);
+        }
+
+        let Some(named) = self.commitments.get_mut(tree_identifier) else {
+            return Err(LedgerError::Config(format!(
+                "no commitment configured for tree '{}'",
+                tree_identifier
+            )));
+        };
+        let committed: Vec<([u8; 32], [u8; 32])> = keys
+            .iter()
+            .zip(&records)
+            .map(|(key, record)| (*key, Self::value_hash(&record.value)))
+            .collect();
+        let commitment = named.kind.build(tree_identifier, &committed);
+
+        let stored_root = storage
+            .get(&Self::root_key(tree_identifier))
+            .map(|value| codec::decode::<TreeRootRecord>(&value));
+        match stored_root {
+            None if keys.is_empty() => {}
+            Some(Ok(record))
+                if record.leaf_count == keys.len() as u64 && record.root == commitment.root() => {}
+            _ => return Err(LedgerError::RootMismatch(tree_identifier.to_string())),
+        }
+
+        for (key, record) in keys.iter().zip(records) {
+            let proof = commitment.prove(key).map(|proof| LedgerProof {
+                tree_identifier: tree_identifier.to_string(),
+                proof_indices: proof.indices,
+                proof_data: proof.data,
+            });
+            self.entries.insert(
+                *key,
+                LedgerEntry {
+                    key: *key,
+                    value: record.value,
+                    proof,
+                    version: record.version,
+                },
+            );
         }
+
+        named.commitment = commitment;
+        Ok(keys.len())
     }
 
     pub fn get_key(&self, value: &LedgerValue) -> [u8; 32] {
-        hash_to_32bit_array(transform(&format!("{:?}", value)))
+        Keccak256::hash(&value.key_bytes())
     }
 
-    pub fn save_entry(
-        &mut self,
-        key: [u8; 32],
-        value: LedgerValue,
-        proof: LedgerProof,
-    ) -> LedgerEntry {
-        let version = self.entries.get(&key).map_or(0, |e| e.version + 1);
-        let entry = LedgerEntry {
-            key,
-            value,
-            proof: Some(proof),
-            version,
+    /// Key of the entry a tree holds, or would hold, for what identifies it:
+    /// a hex address in `accounts`, a hex block hash in `blocks` and a height
+    /// in `mining`. `None` for unknown trees and malformed identifiers.
+    pub fn key_for(&self, tree_identifier: &str, identifier: &str) -> Option<[u8; 32]> {
+        let key_bytes = match tree_identifier {
+            "accounts" => LedgerValue::accounts_key_bytes(&from_hex(identifier).ok()?),
+            "blocks" => LedgerValue::blocks_key_bytes(&hex_to_hash(identifier)?),
+            "mining" => LedgerValue::mining_key_bytes(identifier.parse().ok()?),
+            _ => return None,
         };
+        Some(Keccak256::hash(&key_bytes))
+    }
+
+    /// Key of the difficulty update recorded at `height`, if any.
+    pub fn difficulty_key(&self, height: u64) -> [u8; 32] {
+        Keccak256::hash(&LedgerValue::mining_key_bytes(height))
+    }
+
+    /// Hash of a value as committed to its tree, so updating an entry
+    /// changes the root even though its key stays the same.
+    pub fn value_hash(value: &LedgerValue) -> [u8; 32] {
+        Keccak256::hash(&codec::encode(value))
+    }
+
+    fn commitment(&self, tree_identifier: &str) -> Option<&dyn StateCommitment> {
+        self.commitments
+            .get(tree_identifier)
+            .map(|named| named.commitment.as_ref())
+    }
+
+    fn commitment_mut(&mut self, tree_identifier: &str) -> Option<&mut Box<dyn StateCommitment>> {
+        self.commitments
+            .get_mut(tree_identifier)
+            .map(|named| &mut named.commitment)
+    }
+
+    /// Key of the record holding the entry key stored at `position` of a
+    /// tree. Positions are big-endian so a prefix scan returns the leaves in
+    /// insertion order.
+    pub fn index_key(tree_identifier: &str, position: usize) -> Vec<u8> {
+        [
+            format!("{}{}:", INDEX_PREFIX, tree_identifier).as_bytes(),
+            &(position as u64).to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    pub fn root_key(tree_identifier: &str) -> Vec<u8> {
+        format!("{}{}", ROOT_PREFIX, tree_identifier).into_bytes()
+    }
+
+    fn stage_root(
+        batch: &mut StorageBatch,
+        tree_identifier: &str,
+        commitment: &dyn StateCommitment,
+    ) {
+        let record = TreeRootRecord {
+            root: commitment.root(),
+            leaf_count: commitment.len() as u64,
+        };
+        batch.put(&Self::root_key(tree_identifier), &codec::encode(&record));
+    }
+
+    pub fn save_entry(&mut self, entry: LedgerEntry) {
         println!(
             "Saving entry key: {}, version: {}",
-            to_hex(&key),
+            to_hex(&entry.key),
             entry.version
         );
-        self.entries.insert(key, entry.clone());
-        entry
+        self.entries.insert(entry.key, entry);
     }
 
+    /// Commits the value under `key` in its tree, inserting the key or
+    /// updating the value it already holds, and writes the entry, any new
+    /// index record and the new root in one storage batch. The tree and the
+    /// in-memory entries only keep the change once that batch is on disk.
     pub async fn commit_with_identifier(
         &mut self,
         key: [u8; 32],
         entry_value: LedgerValue,
         tree_identifier: &str,
-        storage: &mut Storage,
+        storage: &mut dyn Storage,
     ) -> Option<LedgerProof> {
-        let (ok, tree_proof_bytes, tree_indices) = {
-            let tree = match tree_identifier {
-                "mining" => &mut self.mining_tree,
-                "accounts" => &mut self.accounts_tree,
-                "blocks" => &mut self.blocks_tree,
-                _ => {
-                    eprintln!("Unknown tree identifier: {}", tree_identifier);
-                    return None;
-                }
+        let value_hash = Self::value_hash(&entry_value);
+        let mut batch = StorageBatch::new();
+        let commitment_proof = {
+            let Some(commitment) = self.commitment_mut(tree_identifier) else {
+                eprintln!("Unknown tree identifier: {}", tree_identifier);
+                return None;
+            };
+
+            let inserted = !commitment.contains(&key);
+            let proof = if inserted {
+                commitment.insert(key, value_hash)
+            } else {
+                commitment.update(key, value_hash)
+            };
+            let Some(proof) = proof else {
+                eprintln!(
+                    "Commitment failed for tree '{}', key {}. Tree automatically rolled back.",
+                    tree_identifier,
+                    to_hex(&key)
+                );
+                return None;
             };
 
-            tree.insert(key);
-            tree.commit()
+            if inserted {
+                let position = commitment.len() - 1;
+                batch.put(&Self::index_key(tree_identifier, position), &key);
+            }
+            Self::stage_root(&mut batch, tree_identifier, commitment.as_ref());
+            proof
         };
 
-        if ok {
-            println!(
-                "Commit successful for tree '{}', key {}",
-                tree_identifier,
-                to_hex(&key)
-            );
-            let proof = LedgerProof {
-                tree_identifier: tree_identifier.to_string(),
-                proof_indices: tree_indices,
-                proof_data: tree_proof_bytes,
-            };
-            let entry = self.save_entry(key, entry_value, proof.clone());
-            let formatted_value = self.format_entry_value(&entry.key, &entry.value);
-            match storage.store(&key, formatted_value).await {
-                Ok(_) => {
-                    println!("Successfully stored entry {} in LevelDB", to_hex(&key));
-                    Some(proof)
-                }
-                Err(e) => {
-                    eprintln!("Failed to store entry {} in LevelDB after commit: {}. Rolling back tree state.", to_hex(&key), e);
-                    match tree_identifier {
-                        "mining" => self.mining_tree.rollback(),
-                        "accounts" => self.accounts_tree.rollback(),
-                        "blocks" => self.blocks_tree.rollback(),
-                        _ => {}
-                    };
-                    None
+        let proof = LedgerProof {
+            tree_identifier: tree_identifier.to_string(),
+            proof_indices: commitment_proof.indices,
+            proof_data: commitment_proof.data,
+        };
+        let entry = LedgerEntry {
+            key,
+            value: entry_value,
+            proof: Some(proof.clone()),
+            version: self.entries.get(&key).map_or(0, |e| e.version + 1),
+        };
+        batch.put(
+            &key,
+            &codec::encode(&EntryRecord {
+                key,
+                value: entry.value.clone(),
+                version: entry.version,
+            }),
+        );
+
+        match storage.write_batch(batch) {
+            Ok(_) => {
+                println!(
+                    "Commit successful for tree '{}', key {}",
+                    tree_identifier,
+                    to_hex(&key)
+                );
+                self.save_entry(entry);
+                Some(proof)
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to store entry {} in LevelDB: {}. Rolling back tree state.",
+                    to_hex(&key),
+                    e
+                );
+                if let Some(commitment) = self.commitment_mut(tree_identifier) {
+                    commitment.rollback();
                 }
+                None
+            }
+        }
+    }
+
+    /// Removes entries from a tree, e.g. blocks disconnected by a reorg. The
+    /// remaining keys keep their order, so the index records above the
+    /// first removed key are rewritten in the same batch as the deletions.
+    pub fn revert_entries(
+        &mut self,
+        keys: &[[u8; 32]],
+        tree_identifier: &str,
+        storage: &mut dyn Storage,
+    ) -> Option<()> {
+        let present: Vec<[u8; 32]> = keys
+            .iter()
+            .filter(|key| self.entries.contains_key(*key))
+            .copied()
+            .collect();
+        if present.is_empty() {
+            return Some(());
+        }
+
+        let Some(named) = self.commitments.get(tree_identifier) else {
+            eprintln!("Unknown tree identifier: {}", tree_identifier);
+            return None;
+        };
+
+        let committed = named.commitment.keys();
+        let remaining: Vec<([u8; 32], [u8; 32])> = committed
+            .iter()
+            .filter(|key| !present.contains(key))
+            .filter_map(|key| {
+                let entry = self.entries.get(key)?;
+                Some((*key, Self::value_hash(&entry.value)))
+            })
+            .collect();
+        let first_changed = committed
+            .iter()
+            .zip(remaining.iter())
+            .take_while(|(key, (kept, _))| key == &kept)
+            .count();
+        let staged = named.kind.build(tree_identifier, &remaining);
+
+        let mut batch = StorageBatch::new();
+        for key in &present {
+            batch.delete(key);
+        }
+        for position in first_changed..committed.len() {
+            match remaining.get(position) {
+                Some((key, _)) => batch.put(&Self::index_key(tree_identifier, position), key),
+                None => batch.delete(&Self::index_key(tree_identifier, position)),
             }
-        } else {
+        }
+        Self::stage_root(&mut batch, tree_identifier, staged.as_ref());
+
+        if let Err(e) = storage.write_batch(batch) {
             eprintln!(
-                "Merkle tree commit failed for tree '{}', key {}. Tree automatically rolled back.",
+                "Failed to revert {} entries of tree '{}': {}",
+                present.len(),
                 tree_identifier,
-                to_hex(&key)
+                e
+            );
+            return None;
+        }
+
+        if let Some(commitment) = self.commitment_mut(tree_identifier) {
+            *commitment = staged;
+        }
+        for key in &present {
+            self.entries.remove(key);
+        }
+        Some(())
+    }
+
+    /// Mirrors a chain outcome in the ledger: entries of disconnected blocks
+    /// and their difficulty updates are reverted before the connected ones
+    /// are committed.
+    pub async fn apply_block_outcome(
+        &mut self,
+        outcome: BlockOutcome,
+        storage: &mut dyn Storage,
+    ) -> Option<()> {
+        let (disconnected, connected, difficulty_updates) = match outcome {
+            BlockOutcome::Extended {
+                block,
+                difficulty_update,
+            } => (vec![], vec![block], difficulty_update.into_iter().collect()),
+            BlockOutcome::SideBranch => (vec![], vec![], vec![]),
+            BlockOutcome::Reorganized {
+                disconnected,
+                connected,
+                difficulty_updates,
+            } => (disconnected, connected, difficulty_updates),
+        };
+
+        let disconnected_difficulty_keys: Vec<[u8; 32]> = disconnected
+            .iter()
+            .map(|block| self.difficulty_key(block.header.height))
+            .collect();
+        self.revert_entries(&disconnected_difficulty_keys, "mining", storage)?;
+
+        let disconnected_keys: Vec<[u8; 32]> = disconnected
+            .into_iter()
+            .map(|block| self.get_key(&LedgerValue::Blocks(block)))
+            .collect();
+        self.revert_entries(&disconnected_keys, "blocks", storage)?;
+
+        for block in connected {
+            let value = LedgerValue::Blocks(block);
+            let key = self.get_key(&value);
+            self.commit_with_identifier(key, value, "blocks", storage)
+                .await?;
+        }
+
+        for update in difficulty_updates {
+            println!(
+                "Recording difficulty change at height {}: {:#010x} -> {:#010x}",
+                update.height, update.previous, update.current
             );
-            None
+            let value = LedgerValue::Mining(update);
+            let key = self.get_key(&value);
+            self.commit_with_identifier(key, value, "mining", storage)
+                .await?;
         }
+
+        Some(())
+    }
+
+    /// Replays the chain outcomes a crash kept from reaching the ledger. The
+    /// chain and the ledger live in separate databases, so a block can be
+    /// stored in one without the other. A block and its difficulty update
+    /// are committed to different trees, so updates of recorded blocks can
+    /// be missing too and are recorded afterwards.
+    pub async fn catch_up(
+        &mut self,
+        blockchain: &Blockchain,
+        storage: &mut dyn Storage,
+    ) -> Option<()> {
+        let recorded = self
+            .entries
+            .values()
+            .filter_map(|entry| match &entry.value {
+                LedgerValue::Blocks(block) => Some(block),
+                _ => None,
+            });
+        let outcome = blockchain.catch_up_outcome(recorded);
+
+        if let BlockOutcome::Reorganized {
+            disconnected,
+            connected,
+            ..
+        } = &outcome
+        {
+            if !disconnected.is_empty() || !connected.is_empty() {
+                println!(
+                    "Ledger is behind the chain: reverting {} blocks and replaying {}",
+                    disconnected.len(),
+                    connected.len()
+                );
+                self.apply_block_outcome(outcome, storage).await?;
+            }
+        }
+
+        let missing_updates: Vec<DifficultyUpdate> = blockchain
+            .difficulty_updates()
+            .into_iter()
+            .filter(|update| {
+                !self
+                    .entries
+                    .contains_key(&self.difficulty_key(update.height))
+            })
+            .collect();
+        for update in missing_updates {
+            println!(
+                "Recording difficulty change at height {} missing from the ledger",
+                update.height
+            );
+            let value = LedgerValue::Mining(update);
+            let key = self.get_key(&value);
+            self.commit_with_identifier(key, value, "mining", storage)
+                .await?;
+        }
+
+        Some(())
     }
 
     pub async fn commit_peer_state(
@@ -153,7 +617,7 @@ impl Ledger {
         key: [u8; 32],
         entry_value: LedgerValue,
         tree_identifier: &str,
-        storage: &mut Storage,
+        storage: &mut dyn Storage,
     ) -> Option<()> {
         self.commit_with_identifier(key, entry_value, tree_identifier, storage)
             .await
@@ -165,28 +629,66 @@ impl Ledger {
         key: [u8; 32],
         entry_value: LedgerValue,
         tree_identifier: &str,
-        storage: &mut Storage,
+        storage: &mut dyn Storage,
     ) -> Option<()> {
         self.commit_peer_state(key, entry_value, tree_identifier, storage)
             .await
     }
 
+    /// Proof of an entry against the current root of its tree. The proof
+    /// stored with an entry only holds for the root it was committed under,
+    /// so later checks prove the entry again.
+    pub fn prove_entry(&self, key: &[u8; 32]) -> Option<LedgerProof> {
+        let tree_identifier = &self.entries.get(key)?.proof.as_ref()?.tree_identifier;
+        let proof = self.commitment(tree_identifier)?.prove(key)?;
+        Some(LedgerProof {
+            tree_identifier: tree_identifier.clone(),
+            proof_indices: proof.indices,
+            proof_data: proof.data,
+        })
+    }
+
     pub fn verify_entry(&self, key: &[u8; 32]) -> bool {
-        if let Some(entry) = self.entries.get(key) {
-            if let Some(proof) = &entry.proof {
-                let tree = match proof.tree_identifier.as_str() {
-                    "mining" => &self.mining_tree,
-                    "accounts" => &self.accounts_tree,
-                    "blocks" => &self.blocks_tree,
-                    _ => return false,
-                };
-                return tree.verify_proof_bytes(&[*key], &proof.proof_indices, &proof.proof_data);
-            }
-        }
-        false
+        let (Some(entry), Some(proof)) = (self.entries.get(key), self.prove_entry(key)) else {
+            return false;
+        };
+        self.commitment(&proof.tree_identifier)
+            .is_some_and(|commitment| {
+                commitment.verify(
+                    key,
+                    &Self::value_hash(&entry.value),
+                    &CommitmentProof {
+                        indices: proof.proof_indices,
+                        data: proof.proof_data,
+                    },
+                )
+            })
+    }
+
+    /// Proof that `key` is not in the named tree, or `None` when it is
+    /// present or the tree's scheme cannot prove absence.
+    pub fn prove_absence(&self, tree_identifier: &str, key: &[u8; 32]) -> Option<Vec<u8>> {
+        self.commitment(tree_identifier)?.prove_absence(key)
+    }
+
+    pub fn verify_absence(&self, tree_identifier: &str, key: &[u8; 32], proof: &[u8]) -> bool {
+        self.commitment(tree_identifier)
+            .is_some_and(|commitment| commitment.verify_absence(key, proof))
+    }
+
+    /// Root of a known tree. An empty tree has the empty root of its scheme,
+    /// see `StateCommitment::root`.
+    pub fn get_tree_root(&self, tree_identifier: &str) -> Option<[u8; 32]> {
+        self.commitment(tree_identifier)
+            .map(|commitment| commitment.root())
     }
 
     pub fn format_entry_value(&mut self, key: &[u8; 32], value: &LedgerValue) -> String {
+        let version = self.entries.get(key).map_or(0, |e| e.version);
+        Self::format_entry_record(key, value, version)
+    }
+
+    fn format_entry_record(key: &[u8; 32], value: &LedgerValue, version: u64) -> String {
         let value_str = match serde_json::to_string(&value) {
             Ok(s) => s,
             Err(e) => {
@@ -203,17 +705,106 @@ impl Ledger {
             "{{\"key\":\"{}\", \"value\":{}, \"version\":{}}}",
             to_hex(key),
             value_str,
-            0,
+            version,
         )
     }
 
-    pub fn get_latest_block_key(&self) -> Option<[u8; 32]> {
-        self.blocks_tree.get_leaves().last().copied()
+    /// Difficulty updates recorded in the mining tree, oldest first.
+    pub fn difficulty_history(&self) -> Vec<([u8; 32], DifficultyUpdate)> {
+        let Some(commitment) = self.commitment("mining") else {
+            return vec![];
+        };
+
+        let mut history: Vec<([u8; 32], DifficultyUpdate)> = commitment
+            .keys()
+            .iter()
+            .filter_map(|key| match &self.entries.get(key)?.value {
+                LedgerValue::Mining(update) => Some((*key, update.clone())),
+                _ => None,
+            })
+            .collect();
+        history.sort_by_key(|(_, update)| update.height);
+        history
+    }
+
+    #[allow(dead_code)]
+    pub fn get_latest_key(&self, tree_identifier: &str) -> Option<[u8; 32]> {
+        self.commitment(tree_identifier)?.keys().last().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    fn config() -> Vec<(String, String)> {
+        [
+            ("mining", "merkle"),
+            ("accounts", "sparse"),
+            ("blocks", "merkle"),
+        ]
+        .iter()
+        .map(|(tree, scheme)| (tree.to_string(), scheme.to_string()))
+        .collect()
+    }
+
+    fn update(height: u64) -> LedgerValue {
+        LedgerValue::Mining(DifficultyUpdate {
+            height,
+            current: 0x1d00ffff,
+            previous: 0x1d00fffe,
+            difference: 1,
+        })
     }
-    pub fn get_latest_account_key(&self) -> Option<[u8; 32]> {
-        self.accounts_tree.get_leaves().last().copied()
+
+    async fn committed(storage: &mut MemoryStorage) -> Ledger {
+        let mut ledger = Ledger::new(&config()).unwrap();
+        for height in [10, 20] {
+            let value = update(height);
+            let key = ledger.get_key(&value);
+            ledger
+                .commit_with_identifier(key, value, "mining", storage)
+                .await
+                .unwrap();
+        }
+        ledger
     }
-    pub fn get_latest_mining_key(&self) -> Option<[u8; 32]> {
-        self.mining_tree.get_leaves().last().copied()
+
+    #[tokio::test]
+    async fn root_records_use_the_canonical_encoding() {
+        let mut storage = MemoryStorage::new();
+        let ledger = committed(&mut storage).await;
+
+        let bytes = storage.get(&Ledger::root_key("mining")).unwrap();
+        assert_eq!(bytes[0], codec::ENCODING_VERSION);
+        let record: TreeRootRecord = codec::decode(&bytes).unwrap();
+        assert_eq!(Some(record.root), ledger.get_tree_root("mining"));
+        assert_eq!(record.leaf_count, 2);
+
+        let loaded = Ledger::load(&mut storage, &config()).unwrap();
+        assert_eq!(
+            loaded.get_tree_root("mining"),
+            ledger.get_tree_root("mining")
+        );
+        assert_eq!(loaded.difficulty_history().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn load_rejects_a_root_record_that_does_not_match() {
+        let mut storage = MemoryStorage::new();
+        committed(&mut storage).await;
+
+        let record = TreeRootRecord {
+            root: [0xaa; 32],
+            leaf_count: 2,
+        };
+        storage
+            .put(&Ledger::root_key("mining"), &codec::encode(&record))
+            .unwrap();
+        assert!(matches!(
+            Ledger::load(&mut storage, &config()),
+            Err(LedgerError::RootMismatch(tree)) if tree == "mining"
+        ));
     }
 }
@@ -1,24 +1,24 @@
-use tokio::sync::Mutex;
+use rusty_leveldb::{LdbIterator, WriteBatch};
 
-pub struct Storage {
+use crate::storage::backend::{BatchOperation, KeyValue, Storage, StorageBatch, StorageError};
+
+pub struct LevelDbStorage {
     db: rusty_leveldb::DB,
-    write_lock: Mutex<()>,
 }
 
-impl Storage {
+impl LevelDbStorage {
     pub fn new(path: &str) -> Self {
         println!("Initializing storage at path: {}", path);
 
-        let mut opts = rusty_leveldb::Options::default();
-        opts.create_if_missing = true;
+        let opts = rusty_leveldb::Options {
+            create_if_missing: true,
+            ..Default::default()
+        };
 
         match rusty_leveldb::DB::open(path, opts) {
             Ok(db) => {
                 println!("Successfully opened database");
-                Storage {
-                    db,
-                    write_lock: Mutex::new(()),
-                }
+                LevelDbStorage { db }
             }
             Err(e) => {
                 eprintln!("Failed to open database: {}", e);
@@ -27,24 +27,72 @@ impl Storage {
         }
     }
 
-    pub async fn store(
-        &mut self,
-        key: &[u8; 32],
-        value: String,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        println!("Storing data with key: {:?}", key);
-
-        let _guard = self.write_lock.lock().await;
+    fn scan_from<F>(&mut self, start: &[u8], in_bounds: F) -> Result<Vec<KeyValue>, StorageError>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut iter = self
+            .db
+            .new_iter()
+            .map_err(|e| StorageError::Iterator(e.to_string()))?;
+        iter.seek(start);
 
-        match self.db.put(key, value.as_bytes()) {
-            Ok(_) => {
-                println!("Successfully stored data");
-                Ok(())
+        let mut entries = vec![];
+        let (mut key, mut value) = (vec![], vec![]);
+        while iter.valid() && iter.current(&mut key, &mut value) {
+            if !in_bounds(&key) {
+                break;
             }
-            Err(e) => {
-                eprintln!("Failed to store key: {}", e);
-                Err(format!("Failed to store key: {}", e).into())
+            entries.push((key.clone(), value.clone()));
+            iter.advance();
+        }
+        Ok(entries)
+    }
+}
+
+impl Storage for LevelDbStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let mut write_batch = WriteBatch::default();
+        write_batch.put(key, value);
+        self.db
+            .write(write_batch, true)
+            .map_err(|e| StorageError::Write(e.to_string()))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get(key)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        let mut write_batch = WriteBatch::default();
+        write_batch.delete(key);
+        self.db
+            .write(write_batch, true)
+            .map_err(|e| StorageError::Delete(e.to_string()))
+    }
+
+    fn write_batch(&mut self, batch: StorageBatch) -> Result<(), StorageError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut write_batch = WriteBatch::default();
+        for operation in batch.into_operations() {
+            match operation {
+                BatchOperation::Put(key, value) => write_batch.put(&key, &value),
+                BatchOperation::Delete(key) => write_batch.delete(&key),
             }
         }
+        self.db
+            .write(write_batch, true)
+            .map_err(|e| StorageError::Batch(e.to_string()))
+    }
+
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Vec<KeyValue>, StorageError> {
+        self.scan_from(prefix, |key| key.starts_with(prefix))
+    }
+
+    fn scan_range(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<KeyValue>, StorageError> {
+        self.scan_from(start, |key| key < end)
     }
 }
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::storage::backend::{BatchOperation, KeyValue, Storage, StorageBatch, StorageError};
+
+/// Ordered in-memory backend for ephemeral nodes. Nothing touches the
+/// filesystem and every write is lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StorageError> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: StorageBatch) -> Result<(), StorageError> {
+        for operation in batch.into_operations() {
+            match operation {
+                BatchOperation::Put(key, value) => self.entries.insert(key, value),
+                BatchOperation::Delete(key) => self.entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn scan_prefix(&mut self, prefix: &[u8]) -> Result<Vec<KeyValue>, StorageError> {
+        Ok(self
+            .entries
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn scan_range(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<KeyValue>, StorageError> {
+        if start >= end {
+            return Ok(vec![]);
+        }
+        Ok(self
+            .entries
+            .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
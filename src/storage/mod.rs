@@ -1,3 +1,9 @@
+pub mod backend;
+pub mod chain_store;
+pub mod commitment;
 pub mod ledger;
 pub mod level_db;
+pub mod memory;
+pub mod sparse_tree;
 pub mod tree;
+pub mod trie;
@@ -0,0 +1,424 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::cryptography::hash::{Hasher, Keccak256};
+use crate::storage::commitment::{CommitmentProof, StateCommitment};
+
+const DEPTH: usize = 256;
+const BITMAP_LEN: usize = DEPTH / 8;
+
+/// Node position: its height above the leaves and the key prefix it covers,
+/// with every bit below that prefix cleared.
+type NodeKey = (u16, [u8; 32]);
+
+/// Hash of an empty subtree at every height, from an empty leaf (height 0)
+/// up to the root of an empty tree (height 256).
+fn default_hashes() -> &'static [[u8; 32]; DEPTH + 1] {
+    static DEFAULTS: OnceLock<[[u8; 32]; DEPTH + 1]> = OnceLock::new();
+    DEFAULTS.get_or_init(|| {
+        let mut defaults = [[0u8; 32]; DEPTH + 1];
+        for height in 1..=DEPTH {
+            defaults[height] = hash_pair(&defaults[height - 1], &defaults[height - 1]);
+        }
+        defaults
+    })
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Keccak256::hash(&[left.as_slice(), right.as_slice()].concat())
+}
+
+fn leaf_hash(key: &[u8; 32], value: &[u8; 32]) -> [u8; 32] {
+    Keccak256::hash(&[&[0u8], key.as_slice(), value.as_slice()].concat())
+}
+
+fn bit(key: &[u8; 32], index: usize) -> bool {
+    key[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+fn flip_bit(key: &mut [u8; 32], index: usize) {
+    key[index / 8] ^= 0x80 >> (index % 8);
+}
+
+/// Prefix of `key` covered by the node at `height` on its path.
+fn prefix(key: &[u8; 32], height: usize) -> [u8; 32] {
+    let kept_bits = DEPTH - height;
+    let mut prefix = *key;
+    for (index, byte) in prefix.iter_mut().enumerate() {
+        let first_bit = index * 8;
+        if first_bit >= kept_bits {
+            *byte = 0;
+        } else if first_bit + 8 > kept_bits {
+            *byte &= 0xff << (first_bit + 8 - kept_bits);
+        }
+    }
+    prefix
+}
+
+/// Sparse Merkle tree over the whole 256-bit key space. Every key has a
+/// fixed position, so the same proof format shows either that a key is set
+/// or that its position is still empty. Only nodes that differ from the
+/// empty subtree are kept.
+pub struct SparseMerkleTree {
+    identifier: String,
+    nodes: HashMap<NodeKey, [u8; 32]>,
+    keys: Vec<[u8; 32]>,
+    last_change: Option<ChangeJournal>,
+}
+
+struct ChangeJournal {
+    previous_nodes: Vec<(NodeKey, Option<[u8; 32]>)>,
+    inserted: bool,
+}
+
+impl SparseMerkleTree {
+    pub fn new(identifier: String) -> Self {
+        Self {
+            identifier,
+            nodes: HashMap::new(),
+            keys: vec![],
+            last_change: None,
+        }
+    }
+
+    pub fn from_entries(identifier: String, entries: &[([u8; 32], [u8; 32])]) -> Self {
+        let mut tree = Self::new(identifier);
+        for (key, value) in entries {
+            tree.set(key, Some(leaf_hash(key, value)), &mut vec![]);
+            tree.keys.push(*key);
+        }
+        tree
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.node(DEPTH, &[0u8; 32])
+    }
+
+    fn node(&self, height: usize, key: &[u8; 32]) -> [u8; 32] {
+        self.nodes
+            .get(&(height as u16, prefix(key, height)))
+            .copied()
+            .unwrap_or(default_hashes()[height])
+    }
+
+    /// Writes a leaf (or clears it with `None`) and rehashes its path,
+    /// recording the previous value of every touched node in `journal`.
+    fn set(
+        &mut self,
+        key: &[u8; 32],
+        leaf: Option<[u8; 32]>,
+        journal: &mut Vec<(NodeKey, Option<[u8; 32]>)>,
+    ) {
+        let defaults = default_hashes();
+        let mut current = leaf.unwrap_or(defaults[0]);
+
+        for (height, default) in defaults.iter().enumerate() {
+            let node_key = (height as u16, prefix(key, height));
+            let previous = if current == *default {
+                self.nodes.remove(&node_key)
+            } else {
+                self.nodes.insert(node_key, current)
+            };
+            journal.push((node_key, previous));
+
+            if height == DEPTH {
+                break;
+            }
+            let mut sibling_key = *key;
+            flip_bit(&mut sibling_key, DEPTH - 1 - height);
+            let sibling = self.node(height, &sibling_key);
+            current = if bit(key, DEPTH - 1 - height) {
+                hash_pair(&sibling, &current)
+            } else {
+                hash_pair(&current, &sibling)
+            };
+        }
+    }
+
+    /// Siblings along the path of `key`, leaf first, with empty subtrees
+    /// left out and marked in a leading 256-bit bitmap instead.
+    pub fn proof_bytes(&self, key: &[u8; 32]) -> Vec<u8> {
+        let defaults = default_hashes();
+        let mut bitmap = [0u8; BITMAP_LEN];
+        let mut siblings = vec![];
+
+        for (height, default) in defaults.iter().enumerate().take(DEPTH) {
+            let mut sibling_key = *key;
+            flip_bit(&mut sibling_key, DEPTH - 1 - height);
+            let sibling = self.node(height, &sibling_key);
+            if sibling != *default {
+                flip_bit(&mut bitmap, height);
+                siblings.extend_from_slice(&sibling);
+            }
+        }
+
+        [bitmap.as_slice(), &siblings].concat()
+    }
+
+    /// Root implied by `proof` when the position of `key` holds `leaf`.
+    fn root_from_proof(key: &[u8; 32], leaf: [u8; 32], proof: &[u8]) -> Option<[u8; 32]> {
+        if proof.len() < BITMAP_LEN || !(proof.len() - BITMAP_LEN).is_multiple_of(32) {
+            return None;
+        }
+        let defaults = default_hashes();
+        let bitmap: [u8; 32] = proof[..BITMAP_LEN].try_into().ok()?;
+        let mut siblings = proof[BITMAP_LEN..].chunks_exact(32);
+        let mut current = leaf;
+
+        for (height, default) in defaults.iter().enumerate().take(DEPTH) {
+            let sibling: [u8; 32] = if bit(&bitmap, height) {
+                siblings.next()?.try_into().ok()?
+            } else {
+                *default
+            };
+            current = if bit(key, DEPTH - 1 - height) {
+                hash_pair(&sibling, &current)
+            } else {
+                hash_pair(&current, &sibling)
+            };
+        }
+
+        siblings.next().is_none().then_some(current)
+    }
+
+    pub fn verify_membership(
+        root: [u8; 32],
+        key: &[u8; 32],
+        value: &[u8; 32],
+        proof: &[u8],
+    ) -> bool {
+        Self::root_from_proof(key, leaf_hash(key, value), proof) == Some(root)
+    }
+
+    pub fn verify_non_membership(root: [u8; 32], key: &[u8; 32], proof: &[u8]) -> bool {
+        Self::root_from_proof(key, default_hashes()[0], proof) == Some(root)
+    }
+
+    fn write_leaf(&mut self, key: [u8; 32], value: [u8; 32], inserted: bool) -> CommitmentProof {
+        let mut journal = ChangeJournal {
+            previous_nodes: vec![],
+            inserted,
+        };
+        self.set(
+            &key,
+            Some(leaf_hash(&key, &value)),
+            &mut journal.previous_nodes,
+        );
+        if inserted {
+            self.keys.push(key);
+        }
+        self.last_change = Some(journal);
+        CommitmentProof {
+            indices: vec![],
+            data: self.proof_bytes(&key),
+        }
+    }
+}
+
+impl StateCommitment for SparseMerkleTree {
+    fn contains(&self, key: &[u8; 32]) -> bool {
+        self.node(0, key) != default_hashes()[0]
+    }
+
+    fn insert(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof> {
+        if self.contains(&key) {
+            return None;
+        }
+        let proof = self.write_leaf(key, value, true);
+        if Self::verify_membership(self.root(), &key, &value, &proof.data) {
+            Some(proof)
+        } else {
+            eprintln!(
+                "Tree '{}': Proof verification FAILED after insert. Rolling back.",
+                self.identifier
+            );
+            self.rollback();
+            None
+        }
+    }
+
+    fn update(&mut self, key: [u8; 32], value: [u8; 32]) -> Option<CommitmentProof> {
+        if !self.contains(&key) {
+            return None;
+        }
+        Some(self.write_leaf(key, value, false))
+    }
+
+    fn rollback(&mut self) {
+        if let Some(journal) = self.last_change.take() {
+            for (node_key, previous) in journal.previous_nodes.into_iter().rev() {
+                match previous {
+                    Some(hash) => self.nodes.insert(node_key, hash),
+                    None => self.nodes.remove(&node_key),
+                };
+            }
+            if journal.inserted {
+                self.keys.pop();
+            }
+        }
+        println!("Tree '{}': Rolled back.", self.identifier);
+    }
+
+    fn keys(&self) -> &[[u8; 32]] {
+        &self.keys
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn root(&self) -> [u8; 32] {
+        SparseMerkleTree::root(self)
+    }
+
+    fn prove(&self, key: &[u8; 32]) -> Option<CommitmentProof> {
+        self.contains(key).then(|| CommitmentProof {
+            indices: vec![],
+            data: self.proof_bytes(key),
+        })
+    }
+
+    fn verify(&self, key: &[u8; 32], value: &[u8; 32], proof: &CommitmentProof) -> bool {
+        Self::verify_membership(self.root(), key, value, &proof.data)
+    }
+
+    fn prove_absence(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        (!self.contains(key)).then(|| self.proof_bytes(key))
+    }
+
+    fn verify_absence(&self, key: &[u8; 32], proof_bytes: &[u8]) -> bool {
+        Self::verify_non_membership(self.root(), key, proof_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keys sharing long prefixes, so proofs carry siblings near the leaves
+    /// as well as near the root.
+    fn entries() -> Vec<([u8; 32], [u8; 32])> {
+        let mut close = [0x5a; 32];
+        close[31] ^= 1;
+        vec![
+            ([0x5a; 32], [0x01; 32]),
+            (close, [0x02; 32]),
+            ([0x00; 32], [0x03; 32]),
+            ([0xff; 32], [0x04; 32]),
+        ]
+    }
+
+    fn tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new("test".to_string());
+        for (key, value) in entries() {
+            tree.insert(key, value).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn membership_proofs_round_trip() {
+        let tree = tree();
+        let root = tree.root();
+        for (key, value) in entries() {
+            let proof = tree.prove(&key).unwrap();
+            assert!(tree.verify(&key, &value, &proof));
+            assert!(!SparseMerkleTree::verify_membership(
+                root,
+                &key,
+                &[0xee; 32],
+                &proof.data
+            ));
+            assert!(!SparseMerkleTree::verify_non_membership(
+                root,
+                &key,
+                &proof.data
+            ));
+            assert!(tree.prove_absence(&key).is_none());
+        }
+    }
+
+    #[test]
+    fn non_membership_proofs_round_trip() {
+        let tree = tree();
+        for key in [[0x5b; 32], [0x01; 32], [0x7f; 32]] {
+            assert!(tree.prove(&key).is_none());
+            let proof = tree.prove_absence(&key).unwrap();
+            assert!(tree.verify_absence(&key, &proof));
+            assert!(!SparseMerkleTree::verify_membership(
+                tree.root(),
+                &key,
+                &[0x01; 32],
+                &proof
+            ));
+        }
+
+        let empty = SparseMerkleTree::new("empty".to_string());
+        let proof = empty.prove_absence(&[0x5a; 32]).unwrap();
+        assert_eq!(proof, vec![0; BITMAP_LEN]);
+        assert_eq!(empty.root(), default_hashes()[DEPTH]);
+        assert!(empty.verify_absence(&[0x5a; 32], &proof));
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        let tree = tree();
+        let (key, value) = entries()[0];
+        let proof = tree.prove(&key).unwrap().data;
+        let verifies =
+            |proof: &[u8]| SparseMerkleTree::verify_membership(tree.root(), &key, &value, proof);
+        assert!(verifies(&proof));
+
+        let bitmap: [u8; 32] = proof[..BITMAP_LEN].try_into().unwrap();
+        let set = (0..DEPTH).find(|height| bit(&bitmap, *height)).unwrap();
+        let unset = (0..DEPTH).find(|height| !bit(&bitmap, *height)).unwrap();
+        for height in [set, unset] {
+            let mut flipped = bitmap;
+            flip_bit(&mut flipped, height);
+            assert!(!verifies(
+                &[flipped.as_slice(), &proof[BITMAP_LEN..]].concat()
+            ));
+        }
+
+        let mut sibling = proof.clone();
+        sibling[BITMAP_LEN] ^= 1;
+        assert!(!verifies(&sibling));
+
+        assert!(!verifies(&proof[..proof.len() - 32]));
+        assert!(!verifies(&[proof.as_slice(), &[0; 32]].concat()));
+        assert!(!verifies(&proof[..proof.len() - 1]));
+    }
+
+    #[test]
+    fn rollback_restores_the_previous_root() {
+        let mut tree = tree();
+        let root = tree.root();
+
+        tree.insert([0x42; 32], [0x05; 32]).unwrap();
+        assert_ne!(tree.root(), root);
+        tree.rollback();
+        assert_eq!(tree.root(), root);
+        assert!(!tree.contains(&[0x42; 32]));
+        assert_eq!(
+            tree.keys(),
+            entries().iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+
+        tree.update([0x5a; 32], [0x06; 32]).unwrap();
+        assert_ne!(tree.root(), root);
+        tree.rollback();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.len(), entries().len());
+    }
+
+    #[test]
+    fn from_entries_matches_incremental_inserts() {
+        let mut reversed = entries();
+        reversed.reverse();
+        let built = SparseMerkleTree::from_entries("built".to_string(), &reversed);
+        assert_eq!(built.root(), tree().root());
+        assert_eq!(
+            built.keys(),
+            reversed.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,98 +1,385 @@
-use rs_merkle::{algorithms::Keccak256, MerkleProof, MerkleTree};
+use rs_merkle::MerkleProof;
 
-struct Keccak256Wrapper(Keccak256);
+use crate::cryptography::hash::{Hasher, Keccak256};
 
+/// Append-only binary Merkle tree with the same shape and hashing as
+/// rs_merkle, where a node without a right sibling moves up unchanged, so
+/// its proofs verify with `MerkleProof`. Every layer is kept, which lets
+/// appending, replacing and proving a leaf touch a single path instead of
+/// the whole tree.
 pub struct Tree {
     identifier: String,
-    tree: MerkleTree<Keccak256>,
+    layers: Vec<Vec<[u8; 32]>>,
+    uncommitted: Vec<[u8; 32]>,
+    history: Vec<usize>,
 }
 
 impl Tree {
+    #[allow(dead_code)]
     pub fn new(identifier: String) -> Self {
+        Self::from_leaves(identifier, &[])
+    }
+
+    pub fn from_leaves(identifier: String, leaves: &[[u8; 32]]) -> Self {
+        let mut layers = vec![leaves.to_vec()];
+        while layers[layers.len() - 1].len() > 1 {
+            let parents = layers[layers.len() - 1]
+                .chunks(2)
+                .map(|pair| Self::parent(&pair[0], pair.get(1)))
+                .collect();
+            layers.push(parents);
+        }
+
         Tree {
             identifier,
-            tree: MerkleTree::<Keccak256>::new(),
+            layers,
+            uncommitted: vec![],
+            history: vec![],
+        }
+    }
+
+    fn parent(left: &[u8; 32], right: Option<&[u8; 32]>) -> [u8; 32] {
+        match right {
+            Some(right) => Keccak256::hash(&[left.as_slice(), right.as_slice()].concat()),
+            None => *left,
+        }
+    }
+
+    /// Rehashes the path from the leaf at `index` up to the root.
+    fn update_path(&mut self, mut index: usize) {
+        let mut height = 0;
+        while self.layers[height].len() > 1 {
+            let layer = &self.layers[height];
+            let parent_index = index / 2;
+            let parent = Self::parent(&layer[parent_index * 2], layer.get(parent_index * 2 + 1));
+
+            if height + 1 == self.layers.len() {
+                self.layers.push(vec![]);
+            }
+            let parents = &mut self.layers[height + 1];
+            if parent_index < parents.len() {
+                parents[parent_index] = parent;
+            } else {
+                parents.push(parent);
+            }
+
+            index = parent_index;
+            height += 1;
+        }
+        self.layers.truncate(height + 1);
+    }
+
+    fn push(&mut self, leaf: [u8; 32]) {
+        self.layers[0].push(leaf);
+        self.update_path(self.layers[0].len() - 1);
+    }
+
+    fn pop(&mut self) {
+        if self.layers[0].pop().is_none() {
+            return;
+        }
+        for height in 1..self.layers.len() {
+            let width = self.layers[height - 1].len().div_ceil(2);
+            self.layers[height].truncate(width);
+        }
+        match self.layers[0].len() {
+            0 => self.layers.truncate(1),
+            len => self.update_path(len - 1),
         }
     }
 
     pub fn insert(&mut self, value: [u8; 32]) {
-        self.tree.insert(value);
+        self.uncommitted.push(value);
     }
 
+    /// Appends the inserted leaves and returns a proof covering only them,
+    /// checked against the new root before it is kept.
     pub fn commit(&mut self) -> (bool, Vec<u8>, Vec<usize>) {
-        self.tree.commit();
-
-        let leaves = self.get_leaves();
-        if leaves.is_empty() {
+        if self.uncommitted.is_empty() {
             return (true, vec![], vec![]);
         }
 
-        let indices_to_prove: Vec<usize> = (0..leaves.len()).collect();
-        let proof_result = self.tree.proof(&indices_to_prove);
-        let proof_bytes = proof_result.to_bytes();
-
-        let root_opt = self.tree.root();
-        if root_opt.is_none() {
-            eprintln!(
-                "Tree '{}': Root is None after commit. Rolling back.",
-                self.identifier
-            );
-            self.rollback();
-            return (false, vec![], vec![]);
+        let first_index = self.len();
+        let inserted = std::mem::take(&mut self.uncommitted);
+        for leaf in &inserted {
+            self.push(*leaf);
         }
-        let root = root_opt.unwrap();
-        let proof_valid = proof_result.verify(root, &indices_to_prove, &leaves, leaves.len());
+        self.history.push(inserted.len());
+
+        let indices_to_prove: Vec<usize> = (first_index..self.len()).collect();
+        let proof_bytes = self.generate_proof_bytes(&indices_to_prove);
+        let proof_valid = self.get_root().is_some_and(|root| {
+            Self::verify_proof_with_root(
+                root,
+                &inserted,
+                &indices_to_prove,
+                &proof_bytes,
+                self.len(),
+            )
+        });
 
         if proof_valid {
-            return (true, proof_bytes, indices_to_prove);
+            (true, proof_bytes, indices_to_prove)
         } else {
             eprintln!(
                 "Tree '{}': Proof verification FAILED after commit. Rolling back.",
                 self.identifier
             );
             self.rollback();
-            return (false, vec![], vec![]);
+            (false, vec![], vec![])
         }
     }
 
-    pub fn get_leaves(&self) -> Vec<[u8; 32]> {
-        self.tree.leaves().unwrap_or_default()
+    /// Replaces a committed leaf and returns the one it held.
+    pub fn set_leaf(&mut self, index: usize, leaf: [u8; 32]) -> Option<[u8; 32]> {
+        let previous = std::mem::replace(self.layers[0].get_mut(index)?, leaf);
+        self.update_path(index);
+        Some(previous)
+    }
+
+    pub fn get_leaves(&self) -> &[[u8; 32]] {
+        &self.layers[0]
     }
 
+    pub fn len(&self) -> usize {
+        self.layers[0].len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.layers[0].is_empty()
+    }
+
+    /// Proof for the given leaves in rs_merkle's format: the missing sibling
+    /// hashes, layer by layer from the leaves up.
     pub fn generate_proof_bytes(&self, indices: &[usize]) -> Vec<u8> {
-        self.tree.proof(indices).to_bytes()
+        let mut current = indices.to_vec();
+        current.sort_unstable();
+        current.dedup();
+
+        let mut proof_bytes = vec![];
+        for layer in &self.layers {
+            for index in &current {
+                let sibling = index ^ 1;
+                if current.binary_search(&sibling).is_err() {
+                    if let Some(hash) = layer.get(sibling) {
+                        proof_bytes.extend_from_slice(hash);
+                    }
+                }
+            }
+            current = current.iter().map(|index| index / 2).collect();
+            current.dedup();
+        }
+        proof_bytes
     }
 
+    /// Drops the leaves added by the last commit, along with any inserted
+    /// leaves that were not committed yet.
     pub fn rollback(&mut self) {
-        self.tree.rollback();
+        self.uncommitted.clear();
+        for _ in 0..self.history.pop().unwrap_or(0) {
+            self.pop();
+        }
         println!("Tree '{}': Rolled back.", self.identifier);
     }
 
+    #[allow(dead_code)]
     pub fn verify_proof_bytes(
         &self,
         leaves_to_verify: &[[u8; 32]],
         indices: &[usize],
         proof_bytes: &[u8],
     ) -> bool {
-        let root = match self.tree.root() {
+        let root = match self.get_root() {
             Some(r) => r,
             None => return false,
         };
-        let total_leaves = self.tree.leaves().map_or(0, |l| l.len());
 
-        match MerkleProof::<Keccak256>::from_bytes(proof_bytes) {
+        Self::verify_proof_with_root(root, leaves_to_verify, indices, proof_bytes, self.len())
+    }
+
+    pub fn verify_proof_with_root(
+        root: [u8; 32],
+        leaves_to_verify: &[[u8; 32]],
+        indices: &[usize],
+        proof_bytes: &[u8],
+        total_leaves: usize,
+    ) -> bool {
+        // Same digest as `Keccak256`; `MerkleProof` needs rs_merkle's hasher.
+        match MerkleProof::<rs_merkle::algorithms::Keccak256>::from_bytes(proof_bytes) {
             Ok(proof) => proof.verify(root, indices, leaves_to_verify, total_leaves),
             Err(_) => false,
         }
     }
 
     pub fn get_root(&self) -> Option<[u8; 32]> {
-        self.tree.root()
+        self.layers.last()?.first().copied()
     }
 
+    #[allow(dead_code)]
     pub fn verify_root(&self, claimed_root: [u8; 32]) -> bool {
-        self.tree
-            .root()
-            .map_or(false, |actual_root| actual_root == claimed_root)
+        self.get_root() == Some(claimed_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_merkle::MerkleTree;
+    use std::time::{Duration, Instant};
+
+    type Reference = MerkleTree<rs_merkle::algorithms::Keccak256>;
+
+    fn leaves(count: usize) -> Vec<[u8; 32]> {
+        (0..count as u64)
+            .map(|index| Keccak256::hash(&index.to_be_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn roots_match_rs_merkle() {
+        assert_eq!(Tree::new("empty".to_string()).get_root(), None);
+
+        let mut incremental = Tree::new("incremental".to_string());
+        for count in 1..=33 {
+            let leaves = leaves(count);
+            let expected = Reference::from_leaves(&leaves).root();
+            assert!(expected.is_some());
+
+            let built = Tree::from_leaves("built".to_string(), &leaves);
+            assert_eq!(built.get_root(), expected, "{} leaves", count);
+
+            incremental.insert(leaves[count - 1]);
+            let (valid, _, indices) = incremental.commit();
+            assert!(valid);
+            assert_eq!(indices, vec![count - 1]);
+            assert_eq!(incremental.get_root(), expected, "{} leaves", count);
+        }
+    }
+
+    #[test]
+    fn set_leaf_matches_rs_merkle_and_can_be_undone() {
+        let mut leaves = leaves(11);
+        let mut tree = Tree::from_leaves("tree".to_string(), &leaves);
+        let root = tree.get_root();
+
+        for index in [0, 5, 10] {
+            let previous = tree.set_leaf(index, [0xaa; 32]).unwrap();
+            assert_eq!(previous, leaves[index]);
+            leaves[index] = [0xaa; 32];
+            assert_eq!(tree.get_root(), Reference::from_leaves(&leaves).root());
+        }
+        for index in [10, 5, 0] {
+            leaves[index] = Keccak256::hash(&(index as u64).to_be_bytes());
+            tree.set_leaf(index, leaves[index]);
+        }
+        assert_eq!(tree.get_root(), root);
+        assert_eq!(tree.set_leaf(11, [0xaa; 32]), None);
+    }
+
+    #[test]
+    fn rollback_restores_earlier_roots() {
+        let leaves = leaves(9);
+        let mut tree = Tree::new("tree".to_string());
+        let mut roots = vec![tree.get_root()];
+        for batch in [&leaves[..4], &leaves[4..5], &leaves[5..]] {
+            for leaf in batch {
+                tree.insert(*leaf);
+            }
+            assert!(tree.commit().0);
+            roots.push(tree.get_root());
+        }
+        assert_eq!(tree.get_root(), Reference::from_leaves(&leaves).root());
+
+        tree.insert([0xbb; 32]);
+        tree.rollback();
+        assert_eq!(tree.get_root(), roots[2]);
+        assert_eq!(tree.len(), 5);
+
+        tree.rollback();
+        assert_eq!(tree.get_root(), roots[1]);
+        tree.rollback();
+        assert_eq!(tree.get_root(), roots[0]);
+        assert!(tree.is_empty());
+
+        tree.insert(leaves[0]);
+        assert!(tree.commit().0);
+        assert_eq!(tree.get_root(), Reference::from_leaves(&leaves[..1]).root());
+    }
+
+    #[test]
+    fn proofs_verify_through_merkle_proof() {
+        for count in [1, 2, 3, 7, 8, 13] {
+            let leaves = leaves(count);
+            let tree = Tree::from_leaves("tree".to_string(), &leaves);
+            let reference = Reference::from_leaves(&leaves);
+            let root = tree.get_root().unwrap();
+
+            let mut index_sets: Vec<Vec<usize>> = (0..count).map(|index| vec![index]).collect();
+            index_sets.push((0..count).step_by(2).collect());
+            index_sets.push(vec![0, count - 1]);
+            for mut indices in index_sets {
+                indices.dedup();
+                let proven: Vec<[u8; 32]> = indices.iter().map(|index| leaves[*index]).collect();
+                let proof = tree.generate_proof_bytes(&indices);
+
+                assert_eq!(proof, reference.proof(&indices).to_bytes());
+                assert!(Tree::verify_proof_with_root(
+                    root, &proven, &indices, &proof, count
+                ));
+                assert!(!Tree::verify_proof_with_root(
+                    root,
+                    &vec![[0xcc; 32]; indices.len()],
+                    &indices,
+                    &proof,
+                    count
+                ));
+            }
+        }
+    }
+
+    /// Commit latency and proof size as the tree grows. Run with
+    /// `cargo test --release commit_latency -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn commit_latency_at_100k_leaves() {
+        const LEAVES: usize = 100_000;
+        const WINDOW: usize = 10_000;
+
+        let mut tree = Tree::new("bench".to_string());
+        let mut elapsed = Duration::ZERO;
+        let mut proof_bytes = 0;
+        for index in 0..LEAVES {
+            let leaf = Keccak256::hash(&(index as u64).to_be_bytes());
+            let start = Instant::now();
+            tree.insert(leaf);
+            let (valid, proof, _) = tree.commit();
+            elapsed += start.elapsed();
+            proof_bytes += proof.len();
+            assert!(valid);
+
+            if (index + 1) % WINDOW == 0 {
+                println!(
+                    "{:>7} leaves: {:>6.2} us/commit, {:>5.1} proof bytes/commit",
+                    index + 1,
+                    elapsed.as_secs_f64() * 1e6 / WINDOW as f64,
+                    proof_bytes as f64 / WINDOW as f64
+                );
+                elapsed = Duration::ZERO;
+                proof_bytes = 0;
+            }
+        }
+
+        let index = LEAVES / 2;
+        let start = Instant::now();
+        let proof = tree.generate_proof_bytes(&[index]);
+        let elapsed = start.elapsed();
+        assert!(tree.verify_proof_bytes(&[tree.get_leaves()[index]], &[index], &proof));
+        println!(
+            "on-demand proof of leaf {}: {:.2} us, {} bytes",
+            index,
+            elapsed.as_secs_f64() * 1e6,
+            proof.len()
+        );
     }
 }
@@ -0,0 +1,720 @@
+use std::{collections::HashMap, fmt};
+
+use crate::cryptography::hash::{Hasher, Keccak256};
+use crate::storage::backend::{Storage, StorageBatch, StorageError};
+use crate::utils::conversion::to_hex;
+
+/// Root of a trie without any keys.
+pub const EMPTY_ROOT: [u8; 32] = [0u8; 32];
+
+const NODE_PREFIX: &[u8] = b"trie:";
+
+const LEAF_TAG: u8 = 0;
+const EXTENSION_TAG: u8 = 1;
+const BRANCH_TAG: u8 = 2;
+
+#[derive(Debug)]
+pub enum TrieError {
+    Storage(StorageError),
+    MissingNode([u8; 32]),
+    CorruptNode([u8; 32]),
+}
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieError::Storage(e) => write!(f, "trie storage error: {}", e),
+            TrieError::MissingNode(hash) => write!(f, "trie node {} is missing", to_hex(hash)),
+            TrieError::CorruptNode(hash) => {
+                write!(f, "trie node {} could not be decoded", to_hex(hash))
+            }
+        }
+    }
+}
+
+/// Trie node. Paths are nibbles and children are always referenced by the
+/// hash of their encoding, which is also their key in storage.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Leaf {
+        path: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Extension {
+        path: Vec<u8>,
+        child: [u8; 32],
+    },
+    Branch {
+        children: [Option<[u8; 32]>; 16],
+        value: Option<Vec<u8>>,
+    },
+}
+
+impl Node {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            Node::Leaf { path, value } => {
+                bytes.push(LEAF_TAG);
+                write_field(&mut bytes, path);
+                write_field(&mut bytes, value);
+            }
+            Node::Extension { path, child } => {
+                bytes.push(EXTENSION_TAG);
+                write_field(&mut bytes, path);
+                bytes.extend_from_slice(child);
+            }
+            Node::Branch { children, value } => {
+                bytes.push(BRANCH_TAG);
+                let bitmap = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child)| child.is_some())
+                    .fold(0u16, |bitmap, (index, _)| bitmap | (1 << index));
+                bytes.extend_from_slice(&bitmap.to_be_bytes());
+                for child in children.iter().flatten() {
+                    bytes.extend_from_slice(child);
+                }
+                match value {
+                    Some(value) => {
+                        bytes.push(1);
+                        write_field(&mut bytes, value);
+                    }
+                    None => bytes.push(0),
+                }
+            }
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (tag, mut rest) = bytes.split_first()?;
+        let node = match *tag {
+            LEAF_TAG => Node::Leaf {
+                path: read_field(&mut rest)?,
+                value: read_field(&mut rest)?,
+            },
+            EXTENSION_TAG => Node::Extension {
+                path: read_field(&mut rest)?,
+                child: read_hash(&mut rest)?,
+            },
+            BRANCH_TAG => {
+                let bitmap = u16::from_be_bytes(read_bytes(&mut rest, 2)?.try_into().ok()?);
+                let mut children = [None; 16];
+                for (index, child) in children.iter_mut().enumerate() {
+                    if bitmap & (1 << index) != 0 {
+                        *child = Some(read_hash(&mut rest)?);
+                    }
+                }
+                let value = match read_bytes(&mut rest, 1)?[0] {
+                    0 => None,
+                    1 => Some(read_field(&mut rest)?),
+                    _ => return None,
+                };
+                Node::Branch { children, value }
+            }
+            _ => return None,
+        };
+        rest.is_empty().then_some(node)
+    }
+
+    fn hash(encoded: &[u8]) -> [u8; 32] {
+        Keccak256::hash(encoded)
+    }
+}
+
+fn write_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+fn read_bytes<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (field, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(field)
+}
+
+fn read_field(bytes: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = u32::from_be_bytes(read_bytes(bytes, 4)?.try_into().ok()?) as usize;
+    read_bytes(bytes, len).map(|field| field.to_vec())
+}
+
+fn read_hash(bytes: &mut &[u8]) -> Option<[u8; 32]> {
+    read_bytes(bytes, 32)?.try_into().ok()
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Merkle Patricia Trie whose nodes live in a `Storage` backend, one record
+/// per node. Nodes are immutable, so any earlier root stays readable and
+/// rolling back is just a matter of restoring that root. Nodes created by
+/// updates are held in memory until `commit` writes them in one batch.
+pub struct PatriciaTrie {
+    root: [u8; 32],
+    storage: Box<dyn Storage>,
+    pending: HashMap<[u8; 32], Vec<u8>>,
+}
+
+impl PatriciaTrie {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self {
+            root: EMPTY_ROOT,
+            storage,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// Points the trie at an earlier root. The nodes of that root must have
+    /// been created by this trie or already be in storage.
+    pub fn set_root(&mut self, root: [u8; 32]) {
+        self.root = root;
+    }
+
+    fn node_key(hash: &[u8; 32]) -> Vec<u8> {
+        [NODE_PREFIX, hash].concat()
+    }
+
+    fn load_encoded(&mut self, hash: &[u8; 32]) -> Result<Vec<u8>, TrieError> {
+        if let Some(encoded) = self.pending.get(hash) {
+            return Ok(encoded.clone());
+        }
+        self.storage
+            .get(&Self::node_key(hash))
+            .ok_or(TrieError::MissingNode(*hash))
+    }
+
+    fn load(&mut self, hash: &[u8; 32]) -> Result<Node, TrieError> {
+        let encoded = self.load_encoded(hash)?;
+        Node::decode(&encoded).ok_or(TrieError::CorruptNode(*hash))
+    }
+
+    fn save(&mut self, node: Node) -> [u8; 32] {
+        let encoded = node.encode();
+        let hash = Node::hash(&encoded);
+        self.pending.insert(hash, encoded);
+        hash
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, TrieError> {
+        let path = to_nibbles(key);
+        let mut remaining = path.as_slice();
+        let mut current = self.root;
+
+        while current != EMPTY_ROOT {
+            match self.load(&current)? {
+                Node::Leaf { path, value } => {
+                    return Ok((path == remaining).then_some(value));
+                }
+                Node::Extension { path, child } => {
+                    if !remaining.starts_with(&path) {
+                        return Ok(None);
+                    }
+                    remaining = &remaining[path.len()..];
+                    current = child;
+                }
+                Node::Branch { children, value } => match remaining.split_first() {
+                    None => return Ok(value),
+                    Some((nibble, rest)) => match children[*nibble as usize] {
+                        Some(child) => {
+                            remaining = rest;
+                            current = child;
+                        }
+                        None => return Ok(None),
+                    },
+                },
+            }
+        }
+        Ok(None)
+    }
+
+    /// Inserts `key` or replaces its value.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), TrieError> {
+        let root = (self.root != EMPTY_ROOT).then_some(self.root);
+        self.root = self.insert_at(root, &to_nibbles(key), value.to_vec())?;
+        Ok(())
+    }
+
+    /// Replaces the value of an existing key and reports whether it existed.
+    #[allow(dead_code)]
+    pub fn update(&mut self, key: &[u8], value: &[u8]) -> Result<bool, TrieError> {
+        if self.get(key)?.is_none() {
+            return Ok(false);
+        }
+        self.insert(key, value)?;
+        Ok(true)
+    }
+
+    #[allow(dead_code)]
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), TrieError> {
+        if self.root == EMPTY_ROOT {
+            return Ok(());
+        }
+        let root = self.root;
+        self.root = self
+            .delete_at(root, &to_nibbles(key))?
+            .unwrap_or(EMPTY_ROOT);
+        Ok(())
+    }
+
+    fn insert_at(
+        &mut self,
+        node: Option<[u8; 32]>,
+        path: &[u8],
+        value: Vec<u8>,
+    ) -> Result<[u8; 32], TrieError> {
+        let Some(hash) = node else {
+            return Ok(self.save(Node::Leaf {
+                path: path.to_vec(),
+                value,
+            }));
+        };
+
+        let node = match self.load(&hash)? {
+            Node::Leaf {
+                path: leaf_path,
+                value: leaf_value,
+            } => {
+                if leaf_path == path {
+                    Node::Leaf {
+                        path: leaf_path,
+                        value,
+                    }
+                } else {
+                    let shared = common_prefix(&leaf_path, path);
+                    let mut children = [None; 16];
+                    let mut branch_value = None;
+                    for (suffix, value) in
+                        [(&leaf_path[shared..], leaf_value), (&path[shared..], value)]
+                    {
+                        match suffix.split_first() {
+                            None => branch_value = Some(value),
+                            Some((nibble, rest)) => {
+                                children[*nibble as usize] = Some(self.save(Node::Leaf {
+                                    path: rest.to_vec(),
+                                    value,
+                                }));
+                            }
+                        }
+                    }
+                    let branch = self.save(Node::Branch {
+                        children,
+                        value: branch_value,
+                    });
+                    return Ok(self.wrap_in_extension(&path[..shared], branch));
+                }
+            }
+            Node::Extension {
+                path: extension_path,
+                child,
+            } => {
+                let shared = common_prefix(&extension_path, path);
+                if shared == extension_path.len() {
+                    let child = self.insert_at(Some(child), &path[shared..], value)?;
+                    Node::Extension {
+                        path: extension_path,
+                        child,
+                    }
+                } else {
+                    let mut children = [None; 16];
+                    let mut branch_value = None;
+
+                    let existing = self.wrap_in_extension(&extension_path[shared + 1..], child);
+                    children[extension_path[shared] as usize] = Some(existing);
+
+                    match path[shared..].split_first() {
+                        None => branch_value = Some(value),
+                        Some((nibble, rest)) => {
+                            children[*nibble as usize] = Some(self.save(Node::Leaf {
+                                path: rest.to_vec(),
+                                value,
+                            }));
+                        }
+                    }
+                    let branch = self.save(Node::Branch {
+                        children,
+                        value: branch_value,
+                    });
+                    return Ok(self.wrap_in_extension(&path[..shared], branch));
+                }
+            }
+            Node::Branch {
+                mut children,
+                value: branch_value,
+            } => match path.split_first() {
+                None => Node::Branch {
+                    children,
+                    value: Some(value),
+                },
+                Some((nibble, rest)) => {
+                    let index = *nibble as usize;
+                    children[index] = Some(self.insert_at(children[index], rest, value)?);
+                    Node::Branch {
+                        children,
+                        value: branch_value,
+                    }
+                }
+            },
+        };
+        Ok(self.save(node))
+    }
+
+    fn wrap_in_extension(&mut self, path: &[u8], child: [u8; 32]) -> [u8; 32] {
+        if path.is_empty() {
+            return child;
+        }
+        self.save(Node::Extension {
+            path: path.to_vec(),
+            child,
+        })
+    }
+
+    /// Returns the new hash of the subtree, or `None` when it became empty.
+    fn delete_at(&mut self, hash: [u8; 32], path: &[u8]) -> Result<Option<[u8; 32]>, TrieError> {
+        match self.load(&hash)? {
+            Node::Leaf {
+                path: leaf_path, ..
+            } => Ok((leaf_path != path).then_some(hash)),
+            Node::Extension {
+                path: extension_path,
+                child,
+            } => {
+                if !path.starts_with(&extension_path) {
+                    return Ok(Some(hash));
+                }
+                match self.delete_at(child, &path[extension_path.len()..])? {
+                    None => Ok(None),
+                    Some(new_child) if new_child == child => Ok(Some(hash)),
+                    Some(new_child) => self.prefix_node(&extension_path, new_child).map(Some),
+                }
+            }
+            Node::Branch {
+                mut children,
+                mut value,
+            } => {
+                match path.split_first() {
+                    None => {
+                        if value.is_none() {
+                            return Ok(Some(hash));
+                        }
+                        value = None;
+                    }
+                    Some((nibble, rest)) => {
+                        let index = *nibble as usize;
+                        let Some(child) = children[index] else {
+                            return Ok(Some(hash));
+                        };
+                        let new_child = self.delete_at(child, rest)?;
+                        if new_child == Some(child) {
+                            return Ok(Some(hash));
+                        }
+                        children[index] = new_child;
+                    }
+                }
+
+                let remaining: Vec<usize> = (0..16).filter(|i| children[*i].is_some()).collect();
+                match (remaining.as_slice(), value) {
+                    ([], None) => Ok(None),
+                    ([], Some(value)) => Ok(Some(self.save(Node::Leaf {
+                        path: vec![],
+                        value,
+                    }))),
+                    ([index], None) => {
+                        let child = children[*index].expect("Remaining child is present");
+                        self.prefix_node(&[*index as u8], child).map(Some)
+                    }
+                    (_, value) => Ok(Some(self.save(Node::Branch { children, value }))),
+                }
+            }
+        }
+    }
+
+    /// Hash of `child` reached through `prefix`, merging the prefix into the
+    /// child when it is a leaf or an extension so the trie stays canonical.
+    fn prefix_node(&mut self, prefix: &[u8], child: [u8; 32]) -> Result<[u8; 32], TrieError> {
+        let node = match self.load(&child)? {
+            Node::Leaf { path, value } => Node::Leaf {
+                path: [prefix, &path].concat(),
+                value,
+            },
+            Node::Extension {
+                path,
+                child: grandchild,
+            } => Node::Extension {
+                path: [prefix, &path].concat(),
+                child: grandchild,
+            },
+            Node::Branch { .. } => Node::Extension {
+                path: prefix.to_vec(),
+                child,
+            },
+        };
+        Ok(self.save(node))
+    }
+
+    /// Writes every node created since the last commit in one batch.
+    pub fn commit(&mut self) -> Result<(), TrieError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut batch = StorageBatch::new();
+        for (hash, encoded) in &self.pending {
+            batch.put(&Self::node_key(hash), encoded);
+        }
+        self.storage
+            .write_batch(batch)
+            .map_err(TrieError::Storage)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Drops every node created since the last commit. The root must be
+    /// pointed back at a committed one first.
+    pub fn discard_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Encoded nodes on the path from the root towards `key`. The same proof
+    /// shows either the value stored under `key` or that the key is absent.
+    pub fn prove(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, TrieError> {
+        let path = to_nibbles(key);
+        let mut remaining = path.as_slice();
+        let mut current = self.root;
+        let mut proof = vec![];
+
+        while current != EMPTY_ROOT {
+            let encoded = self.load_encoded(&current)?;
+            let node = Node::decode(&encoded).ok_or(TrieError::CorruptNode(current))?;
+            proof.push(encoded);
+
+            let next = match node {
+                Node::Leaf { .. } => None,
+                Node::Extension { path, child } => remaining.starts_with(&path).then(|| {
+                    remaining = &remaining[path.len()..];
+                    child
+                }),
+                Node::Branch { children, .. } => {
+                    remaining.split_first().and_then(|(nibble, rest)| {
+                        remaining = rest;
+                        children[*nibble as usize]
+                    })
+                }
+            };
+            match next {
+                Some(child) => current = child,
+                None => break,
+            }
+        }
+        Ok(proof)
+    }
+
+    /// Checks a proof from `prove` against `root`. Returns `None` when the
+    /// proof is invalid, `Some(None)` when it proves `key` is absent and
+    /// `Some(Some(value))` when it proves `key` holds `value`.
+    pub fn verify_proof(root: [u8; 32], key: &[u8], proof: &[Vec<u8>]) -> Option<Option<Vec<u8>>> {
+        let path = to_nibbles(key);
+        let mut remaining = path.as_slice();
+        let mut expected = root;
+        let mut nodes = proof.iter();
+
+        if expected == EMPTY_ROOT {
+            return proof.is_empty().then_some(None);
+        }
+
+        loop {
+            let encoded = nodes.next()?;
+            if Node::hash(encoded) != expected {
+                return None;
+            }
+
+            let next = match Node::decode(encoded)? {
+                Node::Leaf { path, value } => {
+                    return nodes
+                        .next()
+                        .is_none()
+                        .then_some((path == remaining).then_some(value));
+                }
+                Node::Extension { path, child } => {
+                    if !remaining.starts_with(&path) {
+                        return nodes.next().is_none().then_some(None);
+                    }
+                    remaining = &remaining[path.len()..];
+                    child
+                }
+                Node::Branch { children, value } => match remaining.split_first() {
+                    None => return nodes.next().is_none().then_some(value),
+                    Some((nibble, rest)) => match children[*nibble as usize] {
+                        Some(child) => {
+                            remaining = rest;
+                            child
+                        }
+                        None => return nodes.next().is_none().then_some(None),
+                    },
+                },
+            };
+            expected = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+
+    const KEYS: [&[u8]; 6] = [
+        &[0x12, 0x34],
+        &[0x12, 0x35],
+        &[0x12],
+        &[0x12, 0x34, 0x56],
+        &[0xab, 0xcd],
+        &[],
+    ];
+
+    fn empty_trie() -> PatriciaTrie {
+        PatriciaTrie::new(Box::new(MemoryStorage::new()))
+    }
+
+    fn trie_with(keys: &[&[u8]]) -> PatriciaTrie {
+        let mut trie = empty_trie();
+        for key in keys {
+            trie.insert(key, &[key.len() as u8; 3]).unwrap();
+        }
+        trie
+    }
+
+    #[test]
+    fn gets_and_replaces_values() {
+        let mut trie = trie_with(&KEYS);
+        for key in KEYS {
+            assert_eq!(trie.get(key).unwrap(), Some(vec![key.len() as u8; 3]));
+        }
+        assert_eq!(trie.get(&[0x12, 0x3f]).unwrap(), None);
+        assert_eq!(trie.get(&[0x12, 0x34, 0x56, 0x78]).unwrap(), None);
+
+        trie.insert(&[0x12], b"new").unwrap();
+        assert_eq!(trie.get(&[0x12]).unwrap(), Some(b"new".to_vec()));
+        assert!(trie.update(&[0xab, 0xcd], b"updated").unwrap());
+        assert!(!trie.update(&[0xab, 0xce], b"missing").unwrap());
+        assert_eq!(trie.get(&[0xab, 0xce]).unwrap(), None);
+    }
+
+    #[test]
+    fn root_does_not_depend_on_insertion_order() {
+        let forward = trie_with(&KEYS);
+        let reversed: Vec<&[u8]> = KEYS.iter().rev().copied().collect();
+        let backward = trie_with(&reversed);
+        assert_ne!(forward.root(), EMPTY_ROOT);
+        assert_eq!(forward.root(), backward.root());
+    }
+
+    #[test]
+    fn deleting_restores_the_canonical_root() {
+        for (index, key) in KEYS.iter().enumerate() {
+            let mut trie = trie_with(&KEYS);
+            trie.delete(key).unwrap();
+            assert_eq!(trie.get(key).unwrap(), None);
+
+            let others: Vec<&[u8]> = [&KEYS[..index], &KEYS[index + 1..]].concat();
+            assert_eq!(trie.root(), trie_with(&others).root(), "deleting {:?}", key);
+        }
+    }
+
+    #[test]
+    fn deleting_every_key_empties_the_trie() {
+        let mut trie = trie_with(&KEYS);
+        trie.delete(&[0x99]).unwrap();
+        assert_eq!(trie.root(), trie_with(&KEYS).root());
+        for key in KEYS {
+            trie.delete(key).unwrap();
+        }
+        assert_eq!(trie.root(), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn proves_present_and_absent_keys() {
+        let mut trie = trie_with(&KEYS);
+        let root = trie.root();
+        for key in KEYS {
+            let proof = trie.prove(key).unwrap();
+            assert_eq!(
+                PatriciaTrie::verify_proof(root, key, &proof),
+                Some(Some(vec![key.len() as u8; 3]))
+            );
+        }
+        for key in [
+            &[0x12, 0x36][..],
+            &[0xab],
+            &[0xff, 0x00],
+            &[0x12, 0x34, 0x56, 0x78],
+        ] {
+            let proof = trie.prove(key).unwrap();
+            assert_eq!(PatriciaTrie::verify_proof(root, key, &proof), Some(None));
+        }
+
+        let mut empty = empty_trie();
+        let proof = empty.prove(&[0x12]).unwrap();
+        assert!(proof.is_empty());
+        assert_eq!(
+            PatriciaTrie::verify_proof(EMPTY_ROOT, &[0x12], &proof),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        let mut trie = trie_with(&KEYS);
+        let root = trie.root();
+        let proof = trie.prove(&[0x12, 0x34]).unwrap();
+
+        let mut tampered = proof.clone();
+        let last = tampered.last_mut().unwrap();
+        *last.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            PatriciaTrie::verify_proof(root, &[0x12, 0x34], &tampered),
+            None
+        );
+
+        assert_eq!(
+            PatriciaTrie::verify_proof(root, &[0x12, 0x34], &proof[..proof.len() - 1]),
+            None
+        );
+        assert_eq!(
+            PatriciaTrie::verify_proof([0x01; 32], &[0x12, 0x34], &proof),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_earlier_roots_readable_after_commit() {
+        let mut trie = trie_with(&KEYS[..3]);
+        trie.commit().unwrap();
+        let committed = trie.root();
+
+        trie.insert(&[0x12, 0x34], b"changed").unwrap();
+        trie.discard_pending();
+        trie.set_root(committed);
+        assert_eq!(trie.get(&[0x12, 0x34]).unwrap(), Some(vec![2; 3]));
+
+        trie.insert(&[0x77], b"uncommitted").unwrap();
+        let uncommitted = trie.root();
+        trie.discard_pending();
+        trie.set_root(uncommitted);
+        assert!(matches!(trie.get(&[0x77]), Err(TrieError::MissingNode(_))));
+    }
+}
@@ -17,16 +17,13 @@ pub fn from_hex(hex: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
     Ok(bytes)
 }
 
-pub fn public_key_to_address(public_key: &Vec<u8>) -> Vec<u8> {
+/// Decodes a hex encoded 32-byte hash, rejecting any other length.
+pub fn hex_to_hash(hex: &str) -> Option<[u8; 32]> {
+    from_hex(hex).ok()?.try_into().ok()
+}
+
+pub fn public_key_to_address(public_key: &[u8]) -> Vec<u8> {
     let mut address = vec![0u8; 20];
     address.copy_from_slice(&public_key[0..20]);
     address
 }
-
-pub fn hash_to_32bit_array(hash: String) -> [u8; 32] {
-    let bytes = hash.as_bytes();
-    let mut array = [0u8; 32];
-    let len = bytes.len().min(32);
-    array[..len].copy_from_slice(&bytes[..len]);
-    array
-}
@@ -20,6 +20,14 @@ pub fn get_database_path() -> String {
     std::env::var("DATABASE_PATH").unwrap_or_else(|_| "/tmp/ledger".to_string())
 }
 
+pub fn get_chain_database_path() -> String {
+    std::env::var("CHAIN_DATABASE_PATH").unwrap_or_else(|_| "/tmp/chain".to_string())
+}
+
+pub fn get_state_database_path() -> String {
+    std::env::var("STATE_DATABASE_PATH").unwrap_or_else(|_| "/tmp/state".to_string())
+}
+
 pub fn get_peer_addresses() -> Vec<String> {
     match std::env::var("PEER_ADDRESSES") {
         Ok(addrs) => addrs
@@ -30,3 +38,29 @@ pub fn get_peer_addresses() -> Vec<String> {
         Err(_) => Vec::new(),
     }
 }
+
+pub fn get_chain_spec_path() -> Option<String> {
+    std::env::var("CHAIN_SPEC_PATH").ok()
+}
+
+/// Address, in hex, that coinbase transactions of blocks mined by this node
+/// pay. The node does not mine while it is unset.
+pub fn get_miner_address() -> Option<String> {
+    std::env::var("MINER_ADDRESS").ok()
+}
+
+pub fn get_storage_backend() -> String {
+    std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "leveldb".to_string())
+}
+
+/// Commitment scheme of each ledger tree, as `tree=scheme` pairs.
+pub fn get_ledger_commitments() -> Vec<(String, String)> {
+    std::env::var("LEDGER_COMMITMENTS")
+        .unwrap_or_else(|_| "mining=merkle,accounts=sparse,blocks=merkle".to_string())
+        .split(',')
+        .filter_map(|pair| {
+            let (tree, scheme) = pair.split_once('=')?;
+            Some((tree.trim().to_string(), scheme.trim().to_string()))
+        })
+        .collect()
+}